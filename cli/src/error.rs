@@ -0,0 +1,205 @@
+use std::{fmt, io, path::PathBuf};
+
+use frauth_core::{CryptError, DecodeError, EncodeError};
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// The process exit code for invalid arguments
+pub const ARGS_EXIT_CODE: i32 = 2;
+
+/// Everything that can go wrong while running a command
+///
+/// Each kind of error exits with its own code, see [`Error::exit_code`].
+#[derive(Debug)]
+pub enum Error {
+    /// The command can't do what was asked, such as adding a friend twice
+    Command(String),
+
+    /// A local file could not be read or written
+    Io {
+        path: Option<PathBuf>,
+        source: io::Error,
+    },
+
+    /// A local data file is invalid, or could not be rendered
+    Data(String),
+
+    /// A document could not be fetched
+    Network { url: String, kind: NetworkError },
+
+    /// A document is invalid
+    Decode(DecodeError),
+
+    /// A signature or public key can't be trusted
+    Trust(String),
+
+    /// A file could not be encrypted or decrypted
+    Crypt(CryptError),
+
+    /// The passphrase for the secret key was wrong
+    Passphrase,
+}
+
+/// Why a document could not be fetched
+#[derive(Debug)]
+pub enum NetworkError {
+    /// The host name could not be resolved
+    Dns,
+
+    /// The server could not be reached
+    Connect(String),
+
+    /// A secure connection could not be made, or the certificate was rejected
+    Tls(String),
+
+    /// The server responded with something other than success
+    Status(u16),
+
+    /// The response body could not be read
+    Body(String),
+
+    /// Anything else
+    Other(String),
+}
+
+impl Error {
+    /// The process exit code for this error. [`ARGS_EXIT_CODE`] is kept for invalid arguments
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Error::Command(_) => 1,
+            Error::Io { .. } => 3,
+            Error::Data(_) => 4,
+            Error::Network { .. } => 5,
+            Error::Decode(_) => 6,
+            Error::Trust(_) => 7,
+            Error::Crypt(_) => 8,
+            Error::Passphrase => 9,
+        }
+    }
+
+    /// An error for a local file
+    pub fn file(path: impl Into<PathBuf>, source: io::Error) -> Self {
+        Error::Io {
+            path: Some(path.into()),
+            source,
+        }
+    }
+}
+
+impl NetworkError {
+    /// Work out what went wrong with a request made with `surf`
+    pub fn classify(err: &surf::Exception) -> Self {
+        use isahc::Error as E;
+
+        let err = match err.downcast_ref::<isahc::Error>() {
+            Some(err) => err,
+            None => return NetworkError::Other(err.to_string()),
+        };
+
+        let detail = |detail: &Option<String>, fallback: &str| {
+            detail.clone().unwrap_or_else(|| fallback.to_string())
+        };
+
+        match err {
+            E::CouldntResolveHost | E::CouldntResolveProxy => NetworkError::Dns,
+            E::ConnectFailed => NetworkError::Connect("the connection failed".into()),
+            E::NoResponse => NetworkError::Connect("there was no response".into()),
+            E::Timeout => NetworkError::Connect("the request timed out".into()),
+            E::TooManyRedirects => NetworkError::Connect("there were too many redirects".into()),
+            E::SSLConnectFailed(d) | E::SSLEngineError(d) => {
+                NetworkError::Tls(detail(d, "the handshake failed"))
+            }
+            E::BadServerCertificate(d) | E::BadClientCertificate(d) => {
+                NetworkError::Tls(detail(d, "the certificate was rejected"))
+            }
+            E::ResponseBodyError(d) | E::InvalidContentEncoding(d) => {
+                NetworkError::Body(detail(d, "the body is invalid"))
+            }
+            E::InvalidUtf8 => NetworkError::Body("the body is not valid UTF-8".into()),
+            _ => NetworkError::Other(err.to_string()),
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Command(msg) | Error::Data(msg) | Error::Trust(msg) => write!(f, "{}", msg),
+            Error::Io {
+                path: Some(path),
+                source,
+            } => write!(f, "Failed to access file: {}\nReason: {}", path.display(), source),
+            Error::Io { path: None, source } => write!(f, "{}", source),
+            Error::Network { url, kind } => write!(f, "Failed to fetch '{}': {}", url, kind),
+            Error::Decode(e) => write!(f, "Failed to decode: {}", e),
+            Error::Crypt(e) => write!(f, "{}", e),
+            Error::Passphrase => write!(f, "Incorrect passphrase!"),
+        }
+    }
+}
+
+impl fmt::Display for NetworkError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NetworkError::Dns => write!(f, "couldn't resolve the host name (DNS)"),
+            NetworkError::Connect(e) => write!(f, "couldn't connect to the server: {}", e),
+            NetworkError::Tls(e) => write!(f, "couldn't make a secure connection (TLS): {}", e),
+            NetworkError::Status(code) => write!(f, "the server responded with HTTP {}", code),
+            NetworkError::Body(e) => write!(f, "couldn't read the response body: {}", e),
+            NetworkError::Other(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io { source, .. } => Some(source),
+            Error::Decode(e) => Some(e),
+            Error::Crypt(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(source: io::Error) -> Self {
+        Error::Io { path: None, source }
+    }
+}
+
+impl From<DecodeError> for Error {
+    fn from(e: DecodeError) -> Self {
+        Error::Decode(e)
+    }
+}
+
+impl From<EncodeError> for Error {
+    fn from(e: EncodeError) -> Self {
+        Error::Data(e.to_string())
+    }
+}
+
+impl From<CryptError> for Error {
+    fn from(e: CryptError) -> Self {
+        Error::Crypt(e)
+    }
+}
+
+impl From<toml::de::Error> for Error {
+    fn from(e: toml::de::Error) -> Self {
+        Error::Data(format!("Failed to parse data: {}", e))
+    }
+}
+
+impl From<toml::ser::Error> for Error {
+    fn from(e: toml::ser::Error) -> Self {
+        Error::Data(format!("Failed to render data: {}", e))
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(e: serde_json::Error) -> Self {
+        Error::Data(format!("Failed to render data: {}", e))
+    }
+}
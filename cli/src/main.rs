@@ -4,15 +4,31 @@ use directories::ProjectDirs;
 use lazy_static::lazy_static;
 use structopt::StructOpt;
 
-use crate::subcmd::{friend::FriendOpts, me::MeOpts, publish::PublishOpts};
+use crate::{
+    output::Format,
+    subcmd::{
+        crypt::{DecryptOpts, EncryptOpts},
+        friend::FriendOpts,
+        init::InitOpts,
+        me::MeOpts,
+        message::SignMessageOpts,
+        peer::PeerOpts,
+        publish::PublishOpts,
+        sign::SignOpts,
+        verify::VerifyOpts,
+        verify_file::VerifyFileOpts,
+    },
+};
 
 pub mod consts;
+pub mod error;
+pub mod migrate;
+pub mod output;
 pub mod schema;
 pub mod subcmd;
 pub mod util;
 
-pub type Error = Box<dyn std::error::Error>;
-pub type Result<T> = std::result::Result<T, Error>;
+pub use crate::error::{Error, Result};
 
 #[derive(Debug)]
 pub(crate) struct Paths {
@@ -41,16 +57,32 @@ lazy_static! {
     };
 }
 
-/// frauth provides mechanisms to verify identities.
+/// frauth provides mechanisms to verify identities, to sign or verify
+/// messages and files, and to encrypt or decrypt files.
 ///
-/// In the future it will also provide functionality to sign or verify messages
-/// and files, encrypt or decrypt files, and easily discover and verify
-/// friends-of-friends.
+/// In the future it will also provide functionality to easily discover and
+/// verify friends-of-friends.
+#[derive(StructOpt, Debug)]
+#[structopt(rename_all = "kebab-case")]
+struct Opts {
+    /// Answer yes to every confirmation, instead of prompting
+    #[structopt(long = "yes", short = "y", global = true)]
+    yes: bool,
+
+    /// How to print output: text, toml, or json. Only text is printed for
+    /// commands without a documented schema
+    #[structopt(long = "format", global = true, default_value = "text")]
+    format: Format,
+
+    #[structopt(subcommand)]
+    cmd: SubCommands,
+}
+
 #[derive(StructOpt, Debug)]
 #[structopt(rename_all = "kebab-case")]
 enum SubCommands {
     /// Initialize frauth, creating keys and necessary directories
-    Init,
+    Init(InitOpts),
 
     /// Modify your own config
     Me(MeOpts),
@@ -60,23 +92,66 @@ enum SubCommands {
 
     /// Operations around your friend list
     Friend(FriendOpts),
+
+    /// Discover friends-of-friends, and operations around them
+    Peer(PeerOpts),
+
+    /// Create a detached signature for a file
+    Sign(SignOpts),
+
+    /// Verify a detached signature made by one of your friends
+    Verify(VerifyOpts),
+
+    /// Check a published frauth file, without adding it to your friends
+    VerifyFile(VerifyFileOpts),
+
+    /// Clearsign a message read from stdin
+    SignMessage(SignMessageOpts),
+
+    /// Verify a clearsigned message read from stdin, printing the message if valid
+    VerifyMessage,
+
+    /// Encrypt a file to one or more of your friends
+    Encrypt(EncryptOpts),
+
+    /// Decrypt a file sent by one of your friends
+    Decrypt(DecryptOpts),
 }
 
-fn main() -> Result<()> {
-    let opt = SubCommands::from_args();
+fn main() {
+    let matches = match Opts::clap().get_matches_safe() {
+        Ok(matches) => matches,
+        // `--help` and `--version` are reported as errors, but aren't failures
+        Err(e) if !e.use_stderr() => e.exit(),
+        Err(e) => {
+            eprintln!("{}", e.message);
+            ::std::process::exit(error::ARGS_EXIT_CODE);
+        }
+    };
+    let opt = Opts::from_clap(&matches);
+    let yes = opt.yes;
+    let format = opt.format;
 
-    let ret = match opt {
-        SubCommands::Init => subcmd::init::init(),
-        SubCommands::Me(opts) => subcmd::me::me(&opts),
+    let ret = match opt.cmd {
+        SubCommands::Init(opts) => subcmd::init::init(&opts, yes),
+        SubCommands::Me(opts) => subcmd::me::me(&opts, yes, format),
         SubCommands::Publish(opts) => subcmd::publish::publish(&opts),
-        SubCommands::Friend(opts) => subcmd::friend::friend(&opts),
+        SubCommands::Friend(opts) => subcmd::friend::friend(&opts, yes, format),
+        SubCommands::Peer(opts) => subcmd::peer::peer(&opts, format),
+        SubCommands::Sign(opts) => subcmd::sign::sign(&opts),
+        SubCommands::Verify(opts) => subcmd::verify::verify(&opts, format),
+        SubCommands::VerifyFile(opts) => subcmd::verify_file::verify_file(&opts, format),
+        SubCommands::SignMessage(opts) => subcmd::message::sign_message(&opts),
+        SubCommands::VerifyMessage => subcmd::message::verify_message(format),
+        SubCommands::Encrypt(opts) => subcmd::crypt::encrypt(&opts),
+        SubCommands::Decrypt(opts) => subcmd::crypt::decrypt(&opts),
     };
 
-    if ret.is_err() {
-        println!();
+    if let Err(e) = ret {
+        eprintln!();
+        eprintln!("Error: {}", e);
+        ::std::process::exit(e.exit_code());
     }
-
-    ret
 }
 
 pub fn bail(reason: &str) -> ! {
@@ -0,0 +1,253 @@
+use std::{
+    fs::{copy, File},
+    path::{Path, PathBuf},
+};
+
+use toml::{value::Table, Value};
+
+use crate::{
+    consts::{FRIEND_INFO_HEADER, PEER_INFO_HEADER, USER_INFO_HEADER},
+    util::with_suffix,
+    Error, Result, PATHS,
+};
+
+/// The version of the layout of our local data files
+///
+/// Bump this, and add a step to [`MIGRATIONS`], whenever a change would stop
+/// older files from loading.
+pub const DATA_VERSION: i64 = 1;
+
+/// Each step upgrades a file by one version. The step at index `i` upgrades
+/// a file from version `i`
+const MIGRATIONS: &[fn(DataFile, &mut Table) -> Result<()>] = &[from_0_2];
+
+/// One of our local data files
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DataFile {
+    /// `me.frauth`
+    User,
+
+    /// `known.frauth`
+    Friends,
+
+    /// `peer.frauth`
+    Peers,
+}
+
+impl DataFile {
+    pub fn path(self) -> &'static Path {
+        match self {
+            DataFile::User => &PATHS.user_info,
+            DataFile::Friends => &PATHS.friend_info,
+            DataFile::Peers => &PATHS.peer_info,
+        }
+    }
+
+    /// The comment placed at the top of the file
+    pub fn header(self) -> &'static str {
+        match self {
+            DataFile::User => USER_INFO_HEADER,
+            DataFile::Friends => FRIEND_INFO_HEADER,
+            DataFile::Peers => PEER_INFO_HEADER,
+        }
+    }
+}
+
+/// Upgrade the contents of a data file to [`DATA_VERSION`]
+///
+/// Returns the version the file was at, if it had to be upgraded. Files
+/// written by a newer version of frauth are rejected, rather than risking
+/// losing data that this version doesn't understand.
+pub fn migrate(file: DataFile, value: &mut Value) -> Result<Option<i64>> {
+    let path = file.path();
+    let table = value.as_table_mut().ok_or_else(|| {
+        Error::Data(format!(
+            "Failed to parse {}: expected a table",
+            path.display()
+        ))
+    })?;
+
+    // Files from frauth 0.2.x have no version
+    let version = match table.get("version") {
+        None => 0,
+        Some(Value::Integer(version)) if *version >= 0 => *version,
+        Some(_) => {
+            return Err(Error::Data(format!(
+                "Failed to parse {}: `version` should be a positive integer",
+                path.display()
+            )))
+        }
+    };
+
+    if version > DATA_VERSION {
+        return Err(Error::Data(format!(
+            "{} was written by a newer version of frauth (data version {}), please upgrade",
+            path.display(),
+            version
+        )));
+    }
+
+    if version == DATA_VERSION {
+        return Ok(None);
+    }
+
+    for step in MIGRATIONS[version as usize..].iter() {
+        step(file, table)?;
+    }
+    table.insert("version".into(), Value::Integer(DATA_VERSION));
+
+    Ok(Some(version))
+}
+
+/// Keep a copy of a data file as it was before being upgraded from `version`
+///
+/// This is kept apart from the usual `.bak` file, so it survives later saves.
+pub fn backup(file: DataFile, version: i64) -> Result<()> {
+    let path = file.path();
+    let bak_path = backup_path(file, version);
+
+    copy(path, &bak_path)
+        .and_then(|_| File::open(&bak_path)?.sync_all())
+        .map_err(|e| Error::file(&bak_path, e))?;
+
+    eprintln!(
+        "Upgraded {} to data version {}. The old file was kept at {}",
+        path.display(),
+        DATA_VERSION,
+        bak_path.display()
+    );
+
+    Ok(())
+}
+
+/// Where a data file is kept by [`backup`], before being upgraded from `version`
+pub fn backup_path(file: DataFile, version: i64) -> PathBuf {
+    with_suffix(file.path(), &format!(".v{}.bak", version))
+}
+
+/// frauth 0.2.x stored peers with the same layout as friends, without a score
+fn from_0_2(file: DataFile, table: &mut Table) -> Result<()> {
+    if file != DataFile::Peers {
+        return Ok(());
+    }
+
+    let peers = match table.get_mut("map").and_then(Value::as_table_mut) {
+        Some(peers) => peers,
+        None => return Ok(()),
+    };
+
+    for (_, peer) in peers.iter_mut() {
+        let peer = match peer.as_table_mut() {
+            Some(peer) => peer,
+            None => continue,
+        };
+
+        peer.remove("public");
+        peer.entry("score").or_insert(Value::Float(0.0));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        env,
+        fs::{create_dir_all, read_to_string, write},
+        process,
+        sync::Once,
+    };
+
+    use super::*;
+    use crate::util::{load_friends, load_peers};
+
+    /// A peer cache written by frauth 0.2.x, where peers were stored as friends
+    const PEERS_0_2: &str = r#"
+# Peer information, generated by a really early version of frauth.
+#
+# This file is not intended to be edited by hand.
+
+[map."https://example.com/ada.frauth"]
+last_updated = "2020-01-02T03:04:05Z"
+public = true
+
+[map."https://example.com/ada.frauth".info]
+name = "Ada"
+pubkey = "11qYAYKxCrfVS/7TyWQHOg7hcvPapiMlrwIaaPcHURo="
+friends = []
+
+[map."https://example.com/ada.frauth".info.identities]
+github = "ada"
+"#;
+
+    /// Keep the data files of the tests away from the real ones
+    ///
+    /// `PATHS` is only worked out once, so every test shares the same directories.
+    fn use_temp_dirs() {
+        static INIT: Once = Once::new();
+        INIT.call_once(|| {
+            let dir = env::temp_dir().join(format!("frauth-test-{}", process::id()));
+            env::set_var("HOME", &dir);
+            env::set_var("XDG_DATA_HOME", dir.join("data"));
+            env::set_var("XDG_CACHE_HOME", dir.join("cache"));
+
+            assert!(PATHS.base_data.starts_with(&dir));
+            assert!(PATHS.base_cache.starts_with(&dir));
+            create_dir_all(&PATHS.base_data).unwrap();
+            create_dir_all(&PATHS.base_cache).unwrap();
+        });
+    }
+
+    #[test]
+    fn upgrades_0_2_peers() {
+        use_temp_dirs();
+
+        let mut value: Value = toml::from_str(PEERS_0_2).unwrap();
+        assert_eq!(migrate(DataFile::Peers, &mut value).unwrap(), Some(0));
+
+        let peer = &value["map"]["https://example.com/ada.frauth"];
+        assert_eq!(value["version"], Value::Integer(DATA_VERSION));
+        assert_eq!(peer["score"], Value::Float(0.0));
+        assert!(peer.get("public").is_none());
+
+        // Already upgraded files are left alone
+        assert_eq!(migrate(DataFile::Peers, &mut value).unwrap(), None);
+    }
+
+    #[test]
+    fn loading_0_2_peers_keeps_a_backup() {
+        use_temp_dirs();
+
+        let path = DataFile::Peers.path();
+        write(path, PEERS_0_2).unwrap();
+
+        let peers = load_peers().unwrap();
+        assert_eq!(peers.map["https://example.com/ada.frauth"].score, 0.0);
+
+        let backup = backup_path(DataFile::Peers, 0);
+        assert!(backup.ends_with("peer.frauth.v0.bak"));
+        assert_eq!(read_to_string(backup).unwrap(), PEERS_0_2);
+
+        let upgraded = read_to_string(path).unwrap();
+        assert!(upgraded.contains(&format!("version = {}", DATA_VERSION)));
+        assert!(!upgraded.contains("public"));
+    }
+
+    #[test]
+    fn refuses_newer_files() {
+        use_temp_dirs();
+
+        let mut value: Value = toml::from_str("version = 99\n[map]\n").unwrap();
+        assert!(matches!(
+            migrate(DataFile::Friends, &mut value),
+            Err(Error::Data(_))
+        ));
+
+        // The file must be left as it was
+        let path = DataFile::Friends.path();
+        write(path, "version = 99\n[map]\n").unwrap();
+        assert!(matches!(load_friends(), Err(Error::Data(_))));
+        assert_eq!(read_to_string(path).unwrap(), "version = 99\n[map]\n");
+        assert!(!backup_path(DataFile::Friends, 99).exists());
+    }
+}
@@ -0,0 +1,45 @@
+use std::str::FromStr;
+
+use serde::Serialize;
+
+use crate::{Error, Result};
+
+/// How command output is printed
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+    /// Human readable text. This may change between releases
+    Text,
+    /// TOML, using the same layout as the files frauth stores
+    Toml,
+    /// JSON, using the same layout as the TOML output
+    Json,
+}
+
+impl FromStr for Format {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "text" => Ok(Format::Text),
+            "toml" => Ok(Format::Toml),
+            "json" => Ok(Format::Json),
+            _ => Err(Error::Command("expected one of: text, toml, json".into())),
+        }
+    }
+}
+
+/// Print `data` in a machine readable format
+///
+/// Must not be called with [`Format::Text`], as each command prints text its own way.
+pub fn print_structured<T: Serialize>(format: Format, data: &T) -> Result<()> {
+    match format {
+        Format::Toml => print!("{}", toml::to_string(data)?),
+        Format::Json => println!("{}", serde_json::to_string_pretty(data)?),
+        Format::Text => {
+            return Err(Error::Command(
+                "text output is printed by each command".into(),
+            ))
+        }
+    }
+    Ok(())
+}
@@ -1,15 +1,52 @@
-use std::collections::{BTreeMap, HashMap};
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    fmt,
+};
 
+use base64::encode;
 use chrono::{DateTime, Utc};
 use ed25519_dalek::Keypair;
 use serde::{Deserialize, Serialize};
 
+pub use frauth_core::{
+    keystore::EncryptedKey,
+    revocation::Revocation,
+    rotation::KeySuccession,
+    schema::{PublishFriend, PublishUserInfo, Trust},
+};
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct UserInfo {
     pub name: String,
     pub status: Option<String>,
-    pub keypair: Keypair,
+
+    /// The keypair, stored in plaintext. Only set if no passphrase is used
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub keypair: Option<Keypair>,
+
+    /// The URL where this user publishes their frauth file, if known
+    pub url: Option<String>,
     pub identities: HashMap<String, String>,
+
+    /// Statements linking all of our previous keys to the current one
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub successions: Vec<KeySuccession>,
+
+    /// The keypair, with the secret key protected by a passphrase
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub encrypted_key: Option<EncryptedKey>,
+}
+
+impl UserInfo {
+    /// The base64 encoded public key. This never requires the passphrase
+    pub fn pubkey(&self) -> String {
+        match (&self.keypair, &self.encrypted_key) {
+            (Some(keypair), _) => encode(keypair.public.as_bytes()),
+            (None, Some(encrypted)) => encrypted.pubkey.clone(),
+            // Rejected by `load_user_info`
+            (None, None) => String::new(),
+        }
+    }
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize, Default)]
@@ -21,36 +58,205 @@ pub struct Friends {
 pub struct FriendInfo {
     pub last_updated: DateTime<Utc>,
     pub public: bool,
+
+    /// The newest format version this friend has published. Files in older
+    /// versions are refused, as they are signed less strictly
+    #[serde(default = "first_format_version")]
+    pub format_version: u32,
+
+    /// The URL of the friend whose published list this friend's public key
+    /// was taken from. Unset if the key was exchanged directly
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub introduced_by: Option<String>,
+
+    /// What we call this friend, instead of the name they chose. Never published
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub petname: Option<String>,
+
+    /// Free-form notes about this friend. Never published
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub notes: Option<String>,
+
+    /// Labels for selecting groups of friends, such as `work`. Never published
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    pub tags: BTreeSet<String>,
+
+    /// How sure we are that their public key belongs to them. Only published
+    /// with `frauth publish --with-trust`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub trust: Option<Trust>,
+
+    /// How their public key was checked. Unset for friends added before this was recorded
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub verification: Option<Verification>,
+
     pub info: PublishUserInfo,
+
+    /// Set once this friend has announced that their current key should no longer be trusted
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub revoked: Option<Revocation>,
+
+    /// Keys this friend used before rotating to their current key
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub key_history: Vec<PreviousKey>,
+}
+
+/// Friends added before format versions were recorded may only have published version 1
+fn first_format_version() -> u32 {
+    1
+}
+
+impl FriendInfo {
+    /// Our petname for this friend if we set one, otherwise the name they chose
+    pub fn display_name(&self) -> &str {
+        self.petname.as_deref().unwrap_or(&self.info.name)
+    }
+
+    /// Their current key, then each key they used before it, newest first
+    pub fn keys(&self) -> impl Iterator<Item = &str> {
+        std::iter::once(self.info.pubkey.as_str())
+            .chain(self.key_history.iter().rev().map(|k| k.pubkey.as_str()))
+    }
+
+    /// Whether this friend has every one of `tags`
+    pub fn has_tags(&self, tags: &[String]) -> bool {
+        tags.iter().all(|tag| self.tags.contains(tag))
+    }
+}
+
+/// How, and when, we checked that a friend's public key belongs to them
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Verification {
+    pub method: VerifyMethod,
+    pub date: DateTime<Utc>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum VerifyMethod {
+    /// Compared in person
+    InPerson,
+
+    /// Compared over a video call, where we could see and hear them
+    VideoCall,
+
+    /// Taken from the list published by another friend
+    Introduced,
+
+    /// Received some other way, such as by email or text message
+    Unverified,
+}
+
+impl fmt::Display for VerifyMethod {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            VerifyMethod::InPerson => "in-person",
+            VerifyMethod::VideoCall => "video-call",
+            VerifyMethod::Introduced => "introduced",
+            VerifyMethod::Unverified => "unverified",
+        })
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PreviousKey {
+    pub pubkey: String,
+    pub replaced: DateTime<Utc>,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize, Default)]
 pub struct Peers {
-    pub map: BTreeMap<String, FriendInfo>,
+    pub map: BTreeMap<String, PeerInfo>,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct PeerInfo {
     pub last_updated: DateTime<Utc>,
-    pub info: PublishUserInfo,
     pub score: f64,
+    pub info: PublishUserInfo,
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
-pub struct PublishUserInfo {
-    pub name: String,
+/// Your own info, as printed by `frauth me view`. This never contains your secret key
+///
+/// When viewing a single item, only that field is set.
+#[derive(Debug, Default, Serialize)]
+pub struct MeView {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub status: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pubkey: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub identities: Option<HashMap<String, String>>,
+}
+
+/// Who made a valid signature, as printed by `frauth verify` and `frauth verify-message`
+#[derive(Debug, Serialize)]
+pub struct Signer {
+    /// Unset if you signed it yourself, and haven't set your URL
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    pub name: String,
     pub pubkey: String,
+    /// Whether you signed it yourself
+    pub yourself: bool,
+    /// Whether the signer has since revoked this key
+    pub revoked: bool,
+    /// Whether the signer has since rotated away from this key
+    pub rotated: bool,
+}
 
-    // TODO: Reconsider if this should be optional on the next breaking
-    // release. For now keep for 0.2.x compatibility
-    pub last_updated: Option<DateTime<Utc>>,
-    pub friends: Vec<PublishFriend>,
-    pub identities: BTreeMap<String, String>,
+/// A file that passed verification, as printed by `frauth verify-file`
+#[derive(Debug, Serialize)]
+pub struct VerifiedFile {
+    /// The format version the file was published in
+    pub format_version: u32,
+    #[serde(flatten)]
+    pub info: PublishUserInfo,
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
-pub struct PublishFriend {
-    pub uri: String,
+/// A message that passed verification, as printed by `frauth verify-message`
+#[derive(Debug, Serialize)]
+pub struct VerifiedMessage {
+    pub body: String,
+    pub signer: Signer,
+}
+
+/// The outcome of a crawl, as printed by `frauth peer crawl`
+#[derive(Debug, Serialize)]
+pub struct CrawlReport {
+    /// How many peers are now cached
+    pub cached: usize,
+    pub problems: Vec<String>,
+}
+
+/// Every chain vouching for a peer, as printed by `frauth peer why`
+#[derive(Debug, Serialize)]
+pub struct PeerChains {
+    pub score: f64,
+    pub chains: Vec<PeerChain>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct PeerChain {
+    pub hops: Vec<Hop>,
+}
+
+/// One step along a chain of people vouching for a peer
+///
+/// The first hop is always one of your friends, with the public key you
+/// verified. Each later hop has the public key published by the hop before.
+#[derive(Clone, Debug, Serialize)]
+pub struct Hop {
+    pub url: String,
+    pub name: String,
     pub pubkey: String,
+    /// How sure the hop before (or you, for the first hop) is that `pubkey` is theirs
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub trust: Option<Trust>,
+    /// Set if `pubkey` doesn't match the key we cached for this peer
+    pub mismatch: bool,
 }
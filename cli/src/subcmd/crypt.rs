@@ -0,0 +1,166 @@
+use std::{
+    fs::{read, read_to_string, OpenOptions},
+    io::{stdout, Write},
+    path::PathBuf,
+};
+
+use frauth_core::{CryptError, EncryptedFile};
+use structopt::StructOpt;
+
+use crate::{
+    subcmd::friend::parse_tag,
+    util::{create_private_file, load_friends, load_user_info, unlock_keypair, warn_if_revoked},
+    Error, Result,
+};
+
+#[derive(StructOpt, Debug)]
+#[structopt(rename_all = "kebab-case")]
+pub struct EncryptOpts {
+    /// The file to encrypt
+    file: PathBuf,
+
+    /// The URL of a friend to encrypt the file to. Can be given multiple times
+    #[structopt(
+        short = "t",
+        long = "to",
+        required_unless = "to-tag",
+        number_of_values = 1
+    )]
+    to: Vec<String>,
+
+    /// Encrypt the file to every friend with this tag. Can be given multiple times
+    #[structopt(long = "to-tag", number_of_values = 1, parse(try_from_str = parse_tag))]
+    to_tag: Vec<String>,
+
+    /// File to write the encrypted file to. If omitted, `<file>.enc` will be used
+    #[structopt(short = "o", long = "output")]
+    output: Option<PathBuf>,
+}
+
+#[derive(StructOpt, Debug)]
+#[structopt(rename_all = "kebab-case")]
+pub struct DecryptOpts {
+    /// The file to decrypt
+    file: PathBuf,
+
+    /// File to write the decrypted file to. If omitted, the file will be output to stdout
+    #[structopt(short = "o", long = "output")]
+    output: Option<PathBuf>,
+}
+
+pub fn encrypt(opts: &EncryptOpts) -> Result<()> {
+    let user_info = load_user_info()?;
+    let friends = load_friends()?;
+
+    let mut urls = opts.to.iter().collect::<Vec<_>>();
+    for tag in opts.to_tag.iter() {
+        let tagged = friends
+            .map
+            .iter()
+            .filter(|(_, friend)| friend.tags.contains(tag))
+            .map(|(url, _)| url)
+            .collect::<Vec<_>>();
+
+        if tagged.is_empty() {
+            return Err(Error::Command(format!("No friends are tagged '{}'!", tag)));
+        }
+        urls.extend(tagged);
+    }
+    urls.sort();
+    urls.dedup();
+
+    let mut recipients = Vec::with_capacity(urls.len());
+    for url in urls {
+        match friends.map.get(url) {
+            Some(friend) if friend.revoked.is_some() => {
+                warn_if_revoked(url, friend);
+                eprintln!("Refusing to encrypt to '{}'.", url);
+                eprintln!("\nIf they have started over with a new key, remove and re-add them");
+                eprintln!("with `frauth friend add <url>`.");
+                return Err(Error::Trust("Recipient revoked!".into()));
+            }
+            Some(friend) => recipients.push(friend.info.pubkey.as_str()),
+            None => {
+                eprintln!("\nWe don't know about '{}' yet!", url);
+                eprintln!("\nYou can add this friend with `frauth friend add <url>`.");
+                return Err(Error::Command("Friend not known!".into()));
+            }
+        }
+    }
+
+    let data = read(&opts.file).map_err(|e| Error::file(&opts.file, e))?;
+    let keypair = unlock_keypair(&user_info)?;
+    let encrypted = EncryptedFile::encrypt(&data, &keypair, &recipients)?;
+
+    let output = match opts.output {
+        Some(ref path) => path.clone(),
+        None => {
+            let mut path = opts.file.clone().into_os_string();
+            path.push(".enc");
+            path.into()
+        }
+    };
+
+    let mut opt = OpenOptions::new();
+    opt.write(true);
+    opt.truncate(true);
+    opt.create(true);
+
+    let mut file = opt.open(&output).map_err(|e| Error::file(&output, e))?;
+    file.write_all(encrypted.render()?.as_bytes())
+        .map_err(|e| Error::file(&output, e))?;
+
+    println!("Wrote encrypted file to '{}'.", output.display());
+
+    Ok(())
+}
+
+pub fn decrypt(opts: &DecryptOpts) -> Result<()> {
+    let user_info = load_user_info()?;
+    let friends = load_friends()?;
+
+    let input = read_to_string(&opts.file).map_err(|e| Error::file(&opts.file, e))?;
+    let encrypted = EncryptedFile::parse(&input)?;
+
+    let keypair = unlock_keypair(&user_info)?;
+    let data = match encrypted.decrypt(&keypair) {
+        Ok(data) => data,
+        Err(CryptError::NotARecipient) => {
+            eprintln!("\nThis file was not encrypted to you!");
+            return Err(Error::Crypt(CryptError::NotARecipient));
+        }
+        Err(e) => return Err(e.into()),
+    };
+
+    let sender = friends
+        .map
+        .iter()
+        .find(|(_uri, friend)| friend.keys().any(|key| key == encrypted.sender));
+
+    match sender {
+        Some((uri, friend)) => {
+            warn_if_revoked(uri, friend);
+            eprintln!("Decrypted file from '{}' ({})", friend.display_name(), uri);
+            if friend.info.pubkey != encrypted.sender {
+                eprintln!("\nIt was sent with a key they have since rotated away from:");
+                eprintln!("  {}", encrypted.sender);
+            }
+        }
+        None => {
+            eprintln!("\nThis file was sent by an unknown key:");
+            eprintln!("  {}", encrypted.sender);
+            eprintln!("\nIf you know who this is, add them with `frauth friend add <url>`.");
+            return Err(Error::Trust("Unknown sender!".into()));
+        }
+    }
+
+    // The plaintext may well be private, so only we get to read it
+    if let Some(ref path) = opts.output {
+        let mut file = create_private_file(path)?;
+        file.write_all(&data).map_err(|e| Error::file(path, e))?;
+    } else {
+        stdout().write_all(&data)?;
+    }
+
+    Ok(())
+}
@@ -1,22 +1,26 @@
-use std::io::Write;
+use std::{collections::BTreeSet, fmt};
 
 use async_std::task;
-use base64::{decode, encode};
-use chrono::Utc;
-use dialoguer::{Confirmation, Input};
-use ed25519_dalek::PublicKey;
+use chrono::{DateTime, Duration, Utc};
+use frauth_core::{follow_successions, Document};
 use structopt::StructOpt;
-use surf::get;
 use toml::to_string;
 
 use crate::{
-    consts::FRIEND_INFO_HEADER,
-    schema::{FriendInfo, Friends, PublishUserInfo},
-    subcmd::publish::{HEADER_END_OF_FILE, HEADER_SIGNATURE, HEADER_TOP},
-    util::{create_private_file, load_friends, load_user_info},
-    {Error, Result, PATHS},
+    output::{print_structured, Format},
+    schema::{
+        FriendInfo, Friends, PreviousKey, PublishUserInfo, Trust, Verification, VerifyMethod,
+    },
+    util::{
+        confirm, fetch_documents, fetch_versioned, load_friends, load_user_info, prompt_input,
+        save_friends, warn_if_expired, warn_if_revoked, Fetched,
+    },
+    {Error, Result},
 };
 
+/// How far in the future a file may be dated, to allow for clocks that are a little off
+const MAX_FUTURE_HOURS: i64 = 24;
+
 #[derive(StructOpt, Debug)]
 #[structopt(rename_all = "kebab-case")]
 pub enum FriendOpts {
@@ -24,6 +28,26 @@ pub enum FriendOpts {
     Add {
         /// The URL identifying your friend
         url: String,
+
+        /// Take their public key from what this friend publishes, instead of asking you for it
+        #[structopt(long = "via", conflicts_with = "pubkey")]
+        via: Option<String>,
+
+        /// Their public key, instead of asking you for it
+        #[structopt(long = "pubkey")]
+        pubkey: Option<String>,
+
+        /// How you checked their public key: 'in-person', 'video-call' or 'unverified'.
+        /// Defaults to 'unverified'
+        #[structopt(long = "verified", conflicts_with = "via", parse(try_from_str = parse_method))]
+        verified: Option<VerifyMethod>,
+
+        /// How sure you are that the public key is theirs: 'low', 'medium' or 'high'
+        #[structopt(long = "trust", parse(try_from_str = parse_trust))]
+        trust: Option<Trust>,
+
+        #[structopt(flatten)]
+        visibility: VisibilityOpts,
     },
 
     /// Remove a friend from your list
@@ -37,107 +61,301 @@ pub enum FriendOpts {
         /// Show all info for each friend
         #[structopt(long = "detailed", short = "d")]
         detailed: bool,
+
+        /// Only list friends with this tag. Can be given multiple times, to list
+        /// friends with every tag
+        #[structopt(long = "tag", number_of_values = 1, parse(try_from_str = parse_tag))]
+        tags: Vec<String>,
     },
 
     /// Update a friend's info
     Update {
         /// The URL identifying your friend
+        #[structopt(required_unless = "all")]
+        url: Option<String>,
+
+        /// Update every friend, keeping whether each is public, and report what changed
+        #[structopt(long = "all", conflicts_with_all = &["url", "public", "private"])]
+        all: bool,
+
+        /// With `--all`, only update friends with this tag. Can be given multiple
+        /// times, to update friends with every tag
+        #[structopt(
+            long = "tag",
+            number_of_values = 1,
+            requires = "all",
+            parse(try_from_str = parse_tag)
+        )]
+        tags: Vec<String>,
+
+        /// Accept a file that is older than the copy we already have. Only use this
+        /// if you know why, such as your friend restoring a backup
+        #[structopt(long = "allow-older")]
+        allow_older: bool,
+
+        #[structopt(flatten)]
+        visibility: VisibilityOpts,
+    },
+
+    /// Edit your own petname, notes, tags, and how you verified a friend
+    Edit(EditOpts),
+
+    /// Show which of your friends someone else also lists as a friend
+    Mutual {
+        /// The URL identifying the other person
         url: String,
     },
 }
 
-fn check_url(url: &str) -> Result<()> {
+#[derive(StructOpt, Debug)]
+#[structopt(rename_all = "kebab-case")]
+pub struct EditOpts {
+    /// The URL identifying your friend
+    url: String,
+
+    /// What to call this friend, instead of the name they chose
+    #[structopt(long = "petname", conflicts_with = "clear-petname")]
+    petname: Option<String>,
+
+    /// Go back to using the name they chose
+    #[structopt(long = "clear-petname")]
+    clear_petname: bool,
+
+    /// Notes about this friend, replacing any existing notes
+    #[structopt(long = "notes", conflicts_with = "clear-notes")]
+    notes: Option<String>,
+
+    /// Remove your notes about this friend
+    #[structopt(long = "clear-notes")]
+    clear_notes: bool,
+
+    /// Add a tag, such as 'work'. Can be given multiple times
+    #[structopt(long = "tag", number_of_values = 1, parse(try_from_str = parse_tag))]
+    tag: Vec<String>,
+
+    /// Remove a tag. Can be given multiple times
+    #[structopt(long = "untag", number_of_values = 1, parse(try_from_str = parse_tag))]
+    untag: Vec<String>,
+
+    /// Record that you just checked their public key: 'in-person', 'video-call'
+    /// or 'unverified'
+    #[structopt(long = "verified", parse(try_from_str = parse_method))]
+    verified: Option<VerifyMethod>,
+
+    /// How sure you are that the public key is theirs: 'low', 'medium' or 'high'
+    #[structopt(long = "trust", conflicts_with = "clear-trust", parse(try_from_str = parse_trust))]
+    trust: Option<Trust>,
+
+    /// Remove your trust level for this friend
+    #[structopt(long = "clear-trust")]
+    clear_trust: bool,
+}
+
+#[derive(StructOpt, Debug)]
+pub struct VisibilityOpts {
+    /// Make this friend public, instead of asking
+    #[structopt(long = "public", conflicts_with = "private")]
+    public: bool,
+
+    /// Make this friend private, instead of asking
+    #[structopt(long = "private")]
+    private: bool,
+}
+
+pub(crate) fn check_url(url: &str, yes: bool) -> Result<()> {
     if url.starts_with("https://") {
         Ok(())
     } else if url.starts_with("http://") {
         println!("WARNING! It is highly recommended to use HTTPS instead of HTTP.");
         println!();
 
-        let answer = Confirmation::new()
-            .default(false)
-            .with_text("Continue?")
-            .interact()?;
-
-        if answer {
+        if yes || confirm("Continue?", false, "--yes")? {
             println!("Proceeding dangerously.");
             Ok(())
         } else {
-            Err(Error::from("Refusing to use HTTP."))
+            Err(Error::Command("Refusing to use HTTP.".into()))
         }
     } else {
-        Err(Error::from("frauth urls should be served over https!"))
+        Err(Error::Command("frauth urls should be served over https!".into()))
+    }
+}
+
+/// Tags are single words, so they are easy to type and to list
+pub(crate) fn parse_tag(input: &str) -> Result<String> {
+    if input.is_empty() || input.contains(|c: char| c.is_whitespace() || c == ',') {
+        return Err(Error::Command(
+            "tags can't be empty, or contain spaces or commas".into(),
+        ));
+    }
+    Ok(input.to_string())
+}
+
+/// Being introduced is recorded by `frauth friend add --via`, not chosen by hand
+fn parse_method(input: &str) -> Result<VerifyMethod> {
+    match input {
+        "in-person" => Ok(VerifyMethod::InPerson),
+        "video-call" => Ok(VerifyMethod::VideoCall),
+        "unverified" => Ok(VerifyMethod::Unverified),
+        _ => Err(Error::Command(
+            "expected one of: in-person, video-call, unverified".into(),
+        )),
+    }
+}
+
+fn parse_trust(input: &str) -> Result<Trust> {
+    match input {
+        "low" => Ok(Trust::Low),
+        "medium" => Ok(Trust::Medium),
+        "high" => Ok(Trust::High),
+        _ => Err(Error::Command("expected one of: low, medium, high".into())),
+    }
+}
+
+/// Ask whether a friend should be public, unless already decided by a flag
+fn ask_public(visibility: &VisibilityOpts) -> Result<bool> {
+    if visibility.public || visibility.private {
+        return Ok(visibility.public);
     }
+
+    println!("\nShould this friend be public? They will be included in your frauth file the next time you publish.");
+    println!("It is recommended to add friends as public to help build a web of trust.");
+
+    confirm("Make friend public?", true, "--public or --private")
 }
 
-pub fn friend(subcmd: &FriendOpts) -> Result<()> {
+pub fn friend(subcmd: &FriendOpts, yes: bool, format: Format) -> Result<()> {
     let friends = load_friends()?;
 
     match subcmd {
-        FriendOpts::Add { url } => {
-            check_url(url)?;
-            add(url, friends)
+        FriendOpts::Add {
+            url,
+            via,
+            pubkey,
+            verified,
+            trust,
+            visibility,
+        } => {
+            check_url(url, yes)?;
+            let method = match via {
+                Some(_) => VerifyMethod::Introduced,
+                None => verified.unwrap_or(VerifyMethod::Unverified),
+            };
+            add(
+                url,
+                via.as_deref(),
+                pubkey.as_deref(),
+                method,
+                *trust,
+                visibility,
+                friends,
+            )
         }
         FriendOpts::Remove { url } => {
-            check_url(url)?;
+            check_url(url, yes)?;
             remove(url, friends)
         }
-        FriendOpts::List { detailed } => list(*detailed, friends),
-        FriendOpts::Update { url } => {
-            check_url(url)?;
-            update(url, friends)
+        FriendOpts::List { detailed, tags } => list(*detailed, tags, friends, format),
+        FriendOpts::Update {
+            url: Some(url),
+            visibility,
+            allow_older,
+            ..
+        } => {
+            check_url(url, yes)?;
+            update(url, visibility, *allow_older, friends)
         }
+        FriendOpts::Update {
+            url: None,
+            tags,
+            allow_older,
+            ..
+        } => update_all(tags, *allow_older, friends),
+        FriendOpts::Mutual { url } => {
+            check_url(url, yes)?;
+            mutual(url, friends)
+        }
+        FriendOpts::Edit(opts) => edit(opts, friends),
     }
 }
 
-fn add(url: &str, mut friends: Friends) -> Result<()> {
+fn add(
+    url: &str,
+    via: Option<&str>,
+    pubkey: Option<&str>,
+    method: VerifyMethod,
+    trust: Option<Trust>,
+    visibility: &VisibilityOpts,
+    mut friends: Friends,
+) -> Result<()> {
     if friends.map.contains_key(url) {
         eprintln!("\nWe already know about '{}'!", url);
         eprintln!("\nRun `frauth update <url>` to update information about a friend,");
         eprintln!("or remove this friend first with `frauth remove <url>`.");
-        return Err(Error::from("Friend already known!"));
+        return Err(Error::Command("Friend already known!".into()));
     }
 
-    print!(
-        "\nPlease enter `{}`'s public key. You should ask them for it via a separate",
-        url
-    );
-    println!(" route, such as a private message, email, or text message.");
+    let pubkey_maybe_str = match (via, pubkey) {
+        (Some(via), _) => introduced_pubkey(url, via, &friends)?,
+        (None, Some(pubkey)) => pubkey.trim().to_string(),
+        (None, None) => {
+            print!(
+                "\nPlease enter `{}`'s public key. You should ask them for it via a separate",
+                url
+            );
+            println!(" route, such as a private message, email, or text message.");
 
-    println!("\nThey can print their public key on their PC with the command `frauth pubkey`.");
+            println!("\nThey can print their public key on their PC with the command `frauth pubkey`.");
 
-    let pubkey_maybe_str = Input::<String>::new()
-        .with_prompt("Public Key")
-        .interact()?;
+            prompt_input("Public Key", "--pubkey")?
+        }
+    };
 
-    if encode(load_user_info()?.keypair.public.as_bytes()) == pubkey_maybe_str {
+    if load_user_info()?.pubkey() == pubkey_maybe_str {
         eprintln!("\nYou cannot add your own identity as a friend!");
-        return Err(Error::from("Cannot be friends with yourself!"));
+        return Err(Error::Command("Cannot be friends with yourself!".into()));
     }
 
     println!("\nConfirming public key...");
 
-    let pub_info = url_to_pub_info(url)?;
+    let (pub_info, format_version) = url_to_pub_info(url)?;
+
+    check_dates(&pub_info, None, false).map_err(|rejected| explain_rejected(url, rejected))?;
 
     if pub_info.pubkey != pubkey_maybe_str {
-        eprintln!("\nPublic Key Mismatch! Please double check the public key, or ask your friend to re-send.");
-        return Err(Error::from("public key mismatch"));
+        match via {
+            Some(via) => {
+                eprintln!("\nPublic Key Mismatch! '{}' publishes a different key for this friend.", via);
+                eprintln!("Someone may be impersonating them, so check with them directly.");
+            }
+            None => eprintln!("\nPublic Key Mismatch! Please double check the public key, or ask your friend to re-send."),
+        }
+        return Err(Error::Trust("public key mismatch".into()));
     }
 
     println!("\nConfirmed!");
 
-    println!("\nShould this friend be public? They will be included in your frauth file the next time you publish.");
-    println!("It is recommended to add friends as public to help build a web of trust.");
+    warn_if_expired(url, &pub_info);
 
-    let public = Confirmation::new()
-        .with_text("Make friend public?")
-        .interact()?;
+    let public = ask_public(visibility)?;
 
     friends.map.insert(
         url.to_string(),
         FriendInfo {
             info: pub_info,
             public,
+            format_version,
             last_updated: Utc::now(),
+            introduced_by: via.map(str::to_string),
+            petname: None,
+            notes: None,
+            tags: BTreeSet::new(),
+            trust,
+            verification: Some(Verification {
+                method,
+                date: Utc::now(),
+            }),
+            revoked: None,
+            key_history: Vec::new(),
         },
     );
 
@@ -145,13 +363,56 @@ fn add(url: &str, mut friends: Friends) -> Result<()> {
 
     println!("\nAdded '{}' succesfully!", url);
 
+    if method == VerifyMethod::Unverified {
+        println!("\nTheir public key is recorded as unverified. Once you have compared it with");
+        println!("them in person or over a video call, run `frauth friend edit <url> --verified in-person`.");
+    }
+
     Ok(())
 }
 
+/// Look up the public key that an existing friend publishes for `url`
+fn introduced_pubkey(url: &str, via: &str, friends: &Friends) -> Result<String> {
+    let introducer = match friends.map.get(via) {
+        Some(introducer) => introducer,
+        None => {
+            eprintln!("\nWe don't know about '{}' yet!", via);
+            eprintln!("\nOnly friends you have already added can introduce someone new.");
+            return Err(Error::Command("Friend not known!".into()));
+        }
+    };
+
+    if introducer.revoked.is_some() {
+        warn_if_revoked(via, introducer);
+        return Err(Error::Trust("Revoked friends can't introduce anyone!".into()));
+    }
+
+    let listed = introducer.info.friends.iter().find(|f| f.uri == url);
+
+    match listed {
+        Some(listed) => {
+            println!(
+                "\nUsing the public key that {} ({}) publishes for `{}`:",
+                introducer.display_name(),
+                via,
+                url
+            );
+            println!("  {}", listed.pubkey);
+            Ok(listed.pubkey.clone())
+        }
+        None => {
+            eprintln!("\n'{}' doesn't list '{}' as a friend.", via, url);
+            eprintln!("\nIf they added them recently, run `frauth friend update <url>` for");
+            eprintln!("'{}' first, then try again.", via);
+            Err(Error::Command("Friend not listed!".into()))
+        }
+    }
+}
+
 fn remove(url: &str, mut friends: Friends) -> Result<()> {
     if friends.map.remove(url).is_none() {
         eprintln!("\nWe don't know about '{}' yet!", url);
-        return Err(Error::from("Friend not known!"));
+        return Err(Error::Command("Friend not known!".into()));
     }
 
     save_friends(&friends)?;
@@ -161,7 +422,13 @@ fn remove(url: &str, mut friends: Friends) -> Result<()> {
     Ok(())
 }
 
-fn list(detailed: bool, friends: Friends) -> Result<()> {
+fn list(detailed: bool, tags: &[String], mut friends: Friends, format: Format) -> Result<()> {
+    friends.map.retain(|_, friend| friend.has_tags(tags));
+
+    if format != Format::Text {
+        return print_structured(format, &friends);
+    }
+
     for (uri, friend) in friends.map.iter() {
         if detailed {
             println!("{}", uri);
@@ -174,43 +441,82 @@ fn list(detailed: bool, friends: Friends) -> Result<()> {
             //format!("{:#?}", friend.info).lines().map(|l| format!("\t{}", l)).collect::<Vec<_>>().join("\n");
             println!("{}", output);
         } else {
-            println!("{} - {}", friend.info.name, uri);
+            let mut notes = Vec::new();
+            if friend.revoked.is_some() {
+                notes.push("REVOKED".to_string());
+            }
+            if friend.info.is_expired() {
+                notes.push("EXPIRED".to_string());
+            }
+            if let Some(ref via) = friend.introduced_by {
+                notes.push(format!("introduced by {}", via));
+            }
+            match friend.verification {
+                Some(ref v) if v.method != VerifyMethod::Introduced => {
+                    notes.push(v.method.to_string())
+                }
+                _ => {}
+            }
+            if let Some(trust) = friend.trust {
+                notes.push(format!("trust: {}", trust));
+            }
+
+            if !friend.tags.is_empty() {
+                let tags = friend.tags.iter().cloned().collect::<Vec<_>>();
+                notes.push(format!("tags: {}", tags.join(", ")));
+            }
+
+            if notes.is_empty() {
+                println!("{} - {}", friend.display_name(), uri);
+            } else {
+                println!("{} - {} ({})", friend.display_name(), uri, notes.join(", "));
+            }
         }
     }
     Ok(())
 }
 
-fn update(url: &str, mut friends: Friends) -> Result<()> {
+fn update(
+    url: &str,
+    visibility: &VisibilityOpts,
+    allow_older: bool,
+    mut friends: Friends,
+) -> Result<()> {
     if !friends.map.contains_key(url) {
         eprintln!("\nWe don't know about '{}' yet!", url);
         eprintln!("\nYou can add this friend with `frauth add <url>`.");
-        return Err(Error::from("Friend not known!"));
+        return Err(Error::Command("Friend not known!".into()));
     }
 
     println!("\nUpdating information for '{}'...", url);
 
-    let pub_info = url_to_pub_info(url)?;
-
     let mut friend = friends.map[url].clone();
 
-    if pub_info.pubkey != friend.info.pubkey {
-        eprintln!("\nError: `{}`'s public key has changed!", url);
-        eprintln!("\nYou'll need to remove this friend with `frauth friend remove <url>,");
-        eprintln!("Then re-add with `frauth friend add <url>!");
+    match refresh(&mut friend, url_to_document(url)?, allow_older) {
+        Err(rejected) => {
+            warn_if_revoked(url, &friend);
+            return Err(explain_rejected(url, rejected));
+        }
+        Ok(Some(KeyChange::Revoked)) => {
+            warn_if_revoked(url, &friend);
 
-        return Err(Error::from("Public Key Changed!"));
-    }
+            friends.map.insert(url.to_string(), friend);
+            save_friends(&friends)?;
 
-    println!("\nShould this friend be public? They will be included in your frauth file the next time you publish.");
-    println!("It is recommended to add friends as public to help build a web of trust.");
+            println!("Marked '{}' as revoked.", url);
+            return Ok(());
+        }
+        Ok(Some(KeyChange::Rotated)) => {
+            println!("\n`{}` has rotated to a new public key:", url);
+            println!("  {}", friend.info.pubkey);
+            println!("\nThe new key was signed by the old key, so it will be accepted.");
+        }
+        Ok(None) => warn_if_revoked(url, &friend),
+    }
 
-    let public = Confirmation::new()
-        .with_text("Make friend public?")
-        .interact()?;
+    warn_if_expired(url, &friend.info);
 
-    friend.last_updated = Utc::now();
-    friend.info = pub_info;
-    friend.public = public;
+    friend.public = ask_public(visibility)?;
 
     friends.map.insert(url.to_string(), friend);
 
@@ -221,140 +527,669 @@ fn update(url: &str, mut friends: Friends) -> Result<()> {
     Ok(())
 }
 
-fn url_to_pub_info(url: &str) -> Result<PublishUserInfo> {
-    let body_res: Result<String> = task::block_on(async {
-        Ok(get(url)
-            .await
-            .map_err(|_e| Error::from("lol"))?
-            .body_string()
-            .await
-            .map_err(|_e| Error::from("lol"))?)
-    });
-    let body = body_res?;
-    let pub_info = try_from_str(&body)
-        .map_err(|e| Error::from(format!("Failed to decode: {:?}", e).as_str()))?;
-
-    Ok(pub_info)
+fn update_all(tags: &[String], allow_older: bool, mut friends: Friends) -> Result<()> {
+    let urls = friends
+        .map
+        .iter()
+        .filter(|(_, friend)| friend.has_tags(tags))
+        .map(|(url, _)| url.clone())
+        .collect::<Vec<_>>();
+
+    println!("\nUpdating information for {} friend(s)...", urls.len());
+
+    let results = task::block_on(fetch_documents(urls));
+
+    let mut updated = 0;
+    let mut changed = Vec::new();
+    let mut rotated = Vec::new();
+    let mut revoked = Vec::new();
+    let mut expired = Vec::new();
+    let mut rejected = Vec::new();
+    let mut failed = Vec::new();
+
+    for (url, document) in results {
+        let document = match document {
+            Ok(document) => document,
+            Err(e) => {
+                failed.push((url, e.to_string()));
+                continue;
+            }
+        };
+
+        let mut friend = friends.map[&url].clone();
+
+        match refresh(&mut friend, document, allow_older) {
+            Err(reason) => {
+                rejected.push((url, reason));
+                continue;
+            }
+            Ok(Some(KeyChange::Revoked)) => revoked.push(url.clone()),
+            Ok(Some(KeyChange::Rotated)) => rotated.push(url.clone()),
+            Ok(None) => {}
+        }
+
+        let what = changes(&friends.map[&url].info, &friend.info);
+        if !what.is_empty() {
+            changed.push((url.clone(), friend.display_name().to_string(), what));
+        }
+
+        if friend.info.is_expired() {
+            expired.push(url.clone());
+        }
+
+        updated += 1;
+        friends.map.insert(url, friend);
+    }
+
+    save_friends(&friends)?;
+
+    println!("\nUpdated {} friend(s).", updated);
+
+    if !changed.is_empty() {
+        println!("\nChanged their info:");
+        for (url, name, what) in changed.iter() {
+            println!("  - {} - {}: {}", name, url, what.join(", "));
+        }
+    }
+
+    if !rotated.is_empty() {
+        println!("\nRotated to a new public key, signed by their old key:");
+        for url in rotated.iter() {
+            println!("  - {}", url);
+        }
+    }
+
+    if !revoked.is_empty() {
+        println!("\nRevoked their public key:");
+        for url in revoked.iter() {
+            println!("  - {}", url);
+        }
+    }
+
+    if !expired.is_empty() {
+        println!("\nPublished a file that has expired, so it may be stale:");
+        for url in expired.iter() {
+            println!("  - {}", url);
+        }
+    }
+
+    if !rejected.is_empty() {
+        println!("\nNOT updated:");
+        for (url, reason) in rejected.iter() {
+            println!("  - {}: {}", url, reason);
+        }
+        println!("\nRun `frauth friend update <url>` on each of these for details.");
+    }
+
+    if !failed.is_empty() {
+        println!("\nFailed to fetch:");
+        for (url, reason) in failed.iter() {
+            println!("  - {}: {}", url, reason.replace('\n', " "));
+        }
+    }
+
+    if !rejected.is_empty() {
+        Err(Error::Trust("Some files can't be trusted!".into()))
+    } else if !failed.is_empty() {
+        Err(Error::Command("Some friends could not be updated!".into()))
+    } else {
+        Ok(())
+    }
 }
 
+/// How a friend's key changed when their info was refreshed
 #[derive(Debug)]
-enum DecodeError {
-    Layout,
-    Toml,
-    Signature,
-    Verification,
-    PublicKey,
+enum KeyChange {
+    /// They published a revocation of their current key
+    Revoked,
+
+    /// They moved to a new key, signed by their old one
+    Rotated,
 }
 
-fn save_friends(friends: &Friends) -> Result<()> {
-    // TODO: I should do the "save then copy" trick, not overwrite directly.
-    let mut file = create_private_file(&PATHS.friend_info)?;
-    let contents = to_string(&friends)?;
-    file.write_all(FRIEND_INFO_HEADER.as_bytes())?;
-    file.write_all(contents.as_bytes())?;
-    Ok(())
+/// Why a friend's refreshed info can't be trusted
+#[derive(Debug)]
+enum Rejected {
+    /// They revoked a key that isn't the one we know
+    UnknownKeyRevoked,
+
+    /// Their key changed after it was revoked
+    ChangedAfterRevoked,
+
+    /// Their key changed, and the new key wasn't signed by the old one
+    Changed,
+
+    /// The file was signed before the copy we already have, or isn't dated
+    /// when the copy we have is, so an old file may be being replayed
+    Older {
+        stored: DateTime<Utc>,
+        fetched: Option<DateTime<Utc>>,
+    },
+
+    /// The file is dated too far in the future
+    Future(DateTime<Utc>),
+
+    /// The file uses an older format version than they have published before
+    Downgraded { stored: u32, fetched: u32 },
 }
 
-fn try_from_str(input: &str) -> ::std::result::Result<PublishUserInfo, DecodeError> {
-    // TODO: This could probably be done in a way more efficient way
-    // that doesn't require splitting the content into lines and collecting
-    // them and recombining them. But that isn't a big deal for now
-
-    let lines = input.lines().collect::<Vec<_>>();
-
-    // Check 0: There are at least some lines. We need at least:
-    // * A top header
-    // * At least one body line
-    // * A signature header
-    // * A signature
-    // * An End of File footer
-    assert_or(lines.len() >= 5, DecodeError::Layout)?;
-
-    // Check 1: Make sure first line is sane
-    assert_or(
-        lines[0] == HEADER_TOP.trim(),
-        DecodeError::Layout,
-    )?;
-
-    // Check 2: Make sure last line is sane
-    assert_or(
-        lines[lines.len() - 1] == HEADER_END_OF_FILE.trim(),
-        DecodeError::Layout,
-    )?;
-
-    // Check 3: Make sure only one middle divider
-    let lines_body = &lines[1..lines.len()];
-
-    let dividers = lines_body
-        .iter()
-        .enumerate()
-        .filter_map(|(i, x)| {
-            if *x == HEADER_SIGNATURE.trim() {
-                Some(i)
-            } else {
-                None
+impl fmt::Display for Rejected {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Rejected::UnknownKeyRevoked => write!(f, "revoked a key we don't know"),
+            Rejected::ChangedAfterRevoked => write!(f, "new key after their key was revoked"),
+            Rejected::Changed => write!(f, "new key, not signed by the old key"),
+            Rejected::Older {
+                stored,
+                fetched: Some(fetched),
+            } => write!(f, "signed on {}, before the copy we have from {}", fetched, stored),
+            Rejected::Older {
+                stored,
+                fetched: None,
+            } => write!(f, "not dated, but the copy we have is from {}", stored),
+            Rejected::Future(fetched) => write!(f, "dated in the future, on {}", fetched),
+            Rejected::Downgraded { stored, fetched } => write!(
+                f,
+                "format version {}, after they published version {}",
+                fetched, stored
+            ),
+        }
+    }
+}
+
+/// Apply a freshly fetched document to a friend, if it can be trusted
+///
+/// Everything but whether the friend is public is replaced.
+fn refresh(
+    friend: &mut FriendInfo,
+    fetched: Fetched,
+    allow_older: bool,
+) -> std::result::Result<Option<KeyChange>, Rejected> {
+    // Older versions are signed less strictly, so once a friend has published
+    // a newer version, an older file can't stand in for it
+    if fetched.version < friend.format_version {
+        return Err(Rejected::Downgraded {
+            stored: friend.format_version,
+            fetched: fetched.version,
+        });
+    }
+
+    let pub_info = match fetched.document {
+        Document::Identity(pub_info) => pub_info,
+        Document::Revocation(revocation) => {
+            if revocation.pubkey != friend.info.pubkey {
+                return Err(Rejected::UnknownKeyRevoked);
             }
-        })
-        .collect::<Vec<_>>();
 
-    assert_or(dividers.len() == 1, DecodeError::Layout)?;
+            friend.format_version = fetched.version;
+            friend.last_updated = Utc::now();
+            friend.revoked = Some(revocation);
+            return Ok(Some(KeyChange::Revoked));
+        }
+    };
 
-    let pivot = dividers[0];
+    check_dates(&pub_info, Some(&friend.info), allow_older)?;
 
-    assert_or(pivot != 0, DecodeError::Layout)?;
-    assert_or(
-        pivot != (lines_body.len() - 1),
-        DecodeError::Layout,
-    )?;
+    let mut change = None;
 
-    let (toml_body, sig_plus) = lines_body.split_at(pivot);
-    let (_hdr, sig_body_lines) = sig_plus.split_at(1);
+    if pub_info.pubkey != friend.info.pubkey {
+        if friend.revoked.is_some() {
+            return Err(Rejected::ChangedAfterRevoked);
+        }
 
-    assert_or(sig_body_lines.len() == 2, DecodeError::Layout)?;
-    assert_or(!toml_body.is_empty(), DecodeError::Layout)?;
+        let replaced =
+            follow_successions(&pub_info.successions, &friend.info.pubkey, &pub_info.pubkey)
+                .ok_or(Rejected::Changed)?;
+
+        let now = Utc::now();
+        friend
+            .key_history
+            .extend(replaced.into_iter().map(|pubkey| PreviousKey {
+                pubkey,
+                replaced: now,
+            }));
+        change = Some(KeyChange::Rotated);
+    }
 
-    // Check 4: Make sure signature parses
-    let sig_body = sig_body_lines[0];
+    friend.format_version = fetched.version;
+    friend.last_updated = Utc::now();
+    friend.info = pub_info;
 
-    // TODO: The rest of this check probably should just be serde?
-    let sig_decoded = decode(&sig_body.trim()).map_err(|_| DecodeError::Signature)?;
+    Ok(change)
+}
 
-    assert_or(
-        sig_decoded.len() == ed25519_dalek::SIGNATURE_LENGTH,
-        DecodeError::Signature,
-    )?;
+/// Check when a file was signed, against the copy we already have, if any
+///
+/// Newer files replace older ones, so accepting an older file would let a
+/// cache or hostile host undo changes, such as a removed friend.
+fn check_dates(
+    pub_info: &PublishUserInfo,
+    stored: Option<&PublishUserInfo>,
+    allow_older: bool,
+) -> std::result::Result<(), Rejected> {
+    if let Some(fetched) = pub_info.last_updated {
+        if fetched > Utc::now() + Duration::hours(MAX_FUTURE_HOURS) {
+            return Err(Rejected::Future(fetched));
+        }
+    }
 
-    let signature = ed25519_dalek::Signature::from_bytes(sig_decoded.as_ref())
-        .map_err(|_| DecodeError::Signature)?;
+    if allow_older {
+        return Ok(());
+    }
 
-    // Check 5: Make sure toml de-tomls
-    let mut combined = toml_body.join("\n");
-    combined += "\n";
-    let pub_info: PublishUserInfo =
-        toml::from_str(&combined).map_err(|_x| DecodeError::Toml)?;
+    match (stored.and_then(|s| s.last_updated), pub_info.last_updated) {
+        (Some(stored), Some(fetched)) if fetched < stored => Err(Rejected::Older {
+            stored,
+            fetched: Some(fetched),
+        }),
+        (Some(stored), None) => Err(Rejected::Older {
+            stored,
+            fetched: None,
+        }),
+        _ => Ok(()),
+    }
+}
 
-    // Get pubkey from base64
-    let pubkey_bytes = decode(&pub_info.pubkey).map_err(|_| DecodeError::PublicKey)?;
+/// Explain why a friend's info wasn't accepted, and what to do about it
+fn explain_rejected(url: &str, rejected: Rejected) -> Error {
+    match rejected {
+        Rejected::UnknownKeyRevoked => {
+            eprintln!("\nError: `{}` has published a revocation for a key we don't know!", url);
+            Error::Trust("Unknown key revoked!".into())
+        }
+        Rejected::ChangedAfterRevoked => {
+            eprintln!("\nError: `{}`'s public key has changed since it was revoked!", url);
+            eprintln!("\nA revoked key can't vouch for a new key. Check with them directly, then");
+            eprintln!("remove this friend with `frauth friend remove <url>`, and");
+            eprintln!("re-add with `frauth friend add <url>`.");
+            Error::Trust("Public Key Changed!".into())
+        }
+        Rejected::Changed => {
+            eprintln!("\nError: `{}`'s public key has changed!", url);
+            eprintln!("\nThe new key was not signed by the old key. This could mean their");
+            eprintln!("identity has been taken over, so you should check with them directly.");
+            eprintln!("\nYou'll need to remove this friend with `frauth friend remove <url>,");
+            eprintln!("Then re-add with `frauth friend add <url>!");
+            Error::Trust("Public Key Changed!".into())
+        }
+        older @ Rejected::Older { .. } => {
+            eprintln!("\nError: the file at `{}` is older than the copy we already have!", url);
+            eprintln!("It was {}.", older);
+            eprintln!("\nA cache may be serving an old copy, or someone may be replaying an old");
+            eprintln!("file to undo their changes. If you know why, such as your friend");
+            eprintln!("restoring a backup, run again with `--allow-older`.");
+            Error::Trust("Older file served!".into())
+        }
+        Rejected::Future(fetched) => {
+            eprintln!("\nError: the file at `{}` is dated in the future, on {}!", url, fetched);
+            eprintln!("\nTheir clock may be wrong. Ask them to fix it, then re-publish.");
+            Error::Trust("File dated in the future!".into())
+        }
+        downgraded @ Rejected::Downgraded { .. } => {
+            eprintln!("\nError: the file at `{}` uses an older format!", url);
+            eprintln!("It uses {}.", downgraded);
+            eprintln!("\nSomeone may be serving a file from frauth 0.2.x, which is signed less");
+            eprintln!("strictly. If your friend really went back to an older frauth, check with");
+            eprintln!("them directly, then remove and re-add them.");
+            Error::Trust("Older format served!".into())
+        }
+    }
+}
 
-    let public_key =
-        PublicKey::from_bytes(&pubkey_bytes).map_err(|_| DecodeError::PublicKey)?;
+/// Which parts of a friend's published info changed, other than their key
+fn changes(old: &PublishUserInfo, new: &PublishUserInfo) -> Vec<&'static str> {
+    let mut out = Vec::new();
 
-    // Check 6: Make sure toml matches signature
-    let good_sig = public_key.verify(combined.as_bytes(), &signature).is_ok();
+    if old.name != new.name {
+        out.push("name");
+    }
+    if old.status != new.status {
+        out.push("status");
+    }
+    if old.identities != new.identities {
+        out.push("identities");
+    }
 
-    assert_or(good_sig, DecodeError::Verification)?;
+    let friends = |info: &PublishUserInfo| {
+        info.friends
+            .iter()
+            .map(|f| (f.uri.clone(), f.pubkey.clone()))
+            .collect::<BTreeSet<_>>()
+    };
+    if friends(old) != friends(new) {
+        out.push("friends");
+    }
 
-    Ok(pub_info)
+    out
 }
 
-fn assert(me: bool) -> Result<()> {
-    if me {
-        Ok(())
-    } else {
-        Err(Error::from("Failed Assert!"))
+fn edit(opts: &EditOpts, mut friends: Friends) -> Result<()> {
+    let friend = match friends.map.get_mut(&opts.url) {
+        Some(friend) => friend,
+        None => {
+            eprintln!("\nWe don't know about '{}' yet!", opts.url);
+            eprintln!("\nYou can add this friend with `frauth friend add <url>`.");
+            return Err(Error::Command("Friend not known!".into()));
+        }
+    };
+
+    if opts.clear_petname {
+        friend.petname = None;
+    } else if let Some(ref petname) = opts.petname {
+        friend.petname = Some(petname.clone());
+    }
+
+    if opts.clear_notes {
+        friend.notes = None;
+    } else if let Some(ref notes) = opts.notes {
+        friend.notes = Some(notes.clone());
+    }
+
+    for tag in opts.untag.iter() {
+        friend.tags.remove(tag);
+    }
+    friend.tags.extend(opts.tag.iter().cloned());
+
+    if let Some(method) = opts.verified {
+        friend.verification = Some(Verification {
+            method,
+            date: Utc::now(),
+        });
+    }
+
+    if opts.clear_trust {
+        friend.trust = None;
+    } else if let Some(trust) = opts.trust {
+        friend.trust = Some(trust);
+    }
+
+    let friend = friend.clone();
+    save_friends(&friends)?;
+
+    println!("{} - {}", friend.display_name(), opts.url);
+    println!("\nName:     {}", friend.info.name);
+    println!(
+        "Petname:  {}",
+        friend.petname.as_deref().unwrap_or("<no petname is set>")
+    );
+    println!(
+        "Notes:    {}",
+        friend.notes.as_deref().unwrap_or("<no notes>")
+    );
+    let tags = friend.tags.iter().cloned().collect::<Vec<_>>();
+    println!("Tags:     {}", tags.join(", "));
+    match friend.verification {
+        Some(ref v) => println!("Verified: {} on {}", v.method, v.date),
+        None => println!("Verified: <not recorded>"),
+    }
+    match friend.trust {
+        Some(trust) => println!("Trust:    {}", trust),
+        None => println!("Trust:    <no trust level is set>"),
+    }
+
+    Ok(())
+}
+
+fn mutual(url: &str, friends: Friends) -> Result<()> {
+    println!("\nFetching information for '{}'...", url);
+
+    let pub_info = match url_to_document(url)? {
+        Fetched {
+            document: Document::Identity(pub_info),
+            ..
+        } => pub_info,
+        revoked => return record_revocation(url, revoked, friends),
+    };
+
+    warn_if_expired(url, &pub_info);
+
+    if let Some(friend) = friends.map.get(url) {
+        if friend.info.pubkey != pub_info.pubkey {
+            eprintln!("\nWARNING! `{}` is your friend, but their public key has changed!", url);
+            eprintln!("Run `frauth friend update <url>` before trusting this list.");
+        }
+    }
+
+    let mut mutuals = Vec::new();
+    let mut outdated = Vec::new();
+    let mut suspicious = Vec::new();
+
+    for their_friend in pub_info.friends.iter() {
+        let my_friend = match friends.map.get(&their_friend.uri) {
+            Some(my_friend) => my_friend,
+            None => continue,
+        };
+
+        if my_friend.info.pubkey == their_friend.pubkey {
+            mutuals.push((my_friend.display_name(), &their_friend.uri));
+        } else if my_friend
+            .key_history
+            .iter()
+            .any(|old| old.pubkey == their_friend.pubkey)
+        {
+            outdated.push((my_friend.display_name(), &their_friend.uri));
+        } else {
+            suspicious.push((my_friend, their_friend));
+        }
+    }
+
+    if mutuals.is_empty() && outdated.is_empty() && suspicious.is_empty() {
+        println!("\nYou have no friends in common with '{}'.", pub_info.name);
+        return Ok(());
+    }
+
+    if !mutuals.is_empty() {
+        println!("\nFriends in common with '{}':", pub_info.name);
+        for (name, uri) in mutuals {
+            println!("  - {} - {}", name, uri);
+        }
+    }
+
+    if !outdated.is_empty() {
+        println!("\nFriends in common, listed with a key they have since rotated away from:");
+        for (name, uri) in outdated {
+            println!("  - {} - {}", name, uri);
+        }
+    }
+
+    if !suspicious.is_empty() {
+        println!("\nWARNING! These friends are listed with a different public key than yours:");
+        for (my_friend, their_friend) in suspicious {
+            println!("  - {} - {}", my_friend.display_name(), their_friend.uri);
+            println!("      you verified: {}", my_friend.info.pubkey);
+            println!("      they listed:  {}", their_friend.pubkey);
+        }
+        println!("\nOne of you may have been given the wrong key, so check with them directly.");
     }
+
+    Ok(())
+}
+
+/// Mark a friend as revoked after fetching their revocation, as `update` would
+///
+/// This always fails, as there is nothing left to do with a revoked identity.
+fn record_revocation(url: &str, fetched: Fetched, mut friends: Friends) -> Result<()> {
+    let mut friend = match friends.map.get(url) {
+        Some(friend) => friend.clone(),
+        None => return fetched_to_pub_info(url, fetched).map(|_| ()),
+    };
+
+    if let Err(rejected) = refresh(&mut friend, fetched, false) {
+        warn_if_revoked(url, &friend);
+        return Err(explain_rejected(url, rejected));
+    }
+
+    warn_if_revoked(url, &friend);
+
+    friends.map.insert(url.to_string(), friend);
+    save_friends(&friends)?;
+
+    println!("Marked '{}' as revoked.", url);
+
+    Err(Error::Trust("Identity revoked!".into()))
+}
+
+fn url_to_pub_info(url: &str) -> Result<(PublishUserInfo, u32)> {
+    fetched_to_pub_info(url, url_to_document(url)?)
 }
 
-fn assert_or<E>(me: bool, err: E) -> ::std::result::Result<(), E> {
-    assert(me).map_err(|_| err)
+fn fetched_to_pub_info(url: &str, fetched: Fetched) -> Result<(PublishUserInfo, u32)> {
+    match fetched.document {
+        Document::Identity(pub_info) => Ok((pub_info, fetched.version)),
+        Document::Revocation(revocation) => {
+            eprintln!(
+                "\n`{}` revoked their public key on {}.",
+                url, revocation.revoked_at
+            );
+            if let Some(ref reason) = revocation.reason {
+                eprintln!("Reason: {}", reason);
+            }
+            Err(Error::Trust("Identity revoked!".into()))
+        }
+    }
+}
+
+fn url_to_document(url: &str) -> Result<Fetched> {
+    task::block_on(fetch_versioned(url))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PUBKEY: &str = "11qYAYKxCrfVS/7TyWQHOg7hcvPapiMlrwIaaPcHURo=";
+
+    fn pub_info(last_updated: Option<DateTime<Utc>>) -> PublishUserInfo {
+        PublishUserInfo {
+            name: "Ada".into(),
+            status: None,
+            pubkey: PUBKEY.into(),
+            last_updated,
+            expires: None,
+            friends: Vec::new(),
+            identities: Default::default(),
+            successions: Vec::new(),
+        }
+    }
+
+    fn known_friend(last_updated: Option<DateTime<Utc>>) -> FriendInfo {
+        FriendInfo {
+            last_updated: Utc::now(),
+            public: false,
+            format_version: 2,
+            introduced_by: None,
+            petname: None,
+            notes: None,
+            tags: BTreeSet::new(),
+            trust: None,
+            verification: None,
+            info: pub_info(last_updated),
+            revoked: None,
+            key_history: Vec::new(),
+        }
+    }
+
+    fn fetched(version: u32, last_updated: Option<DateTime<Utc>>) -> Fetched {
+        Fetched {
+            document: Document::Identity(pub_info(last_updated)),
+            version,
+        }
+    }
+
+    #[test]
+    fn newer_files_are_accepted() {
+        let stored = Utc::now() - Duration::days(2);
+        let newer = Utc::now() - Duration::days(1);
+
+        let mut friend = known_friend(Some(stored));
+        assert!(matches!(
+            refresh(&mut friend, fetched(2, Some(newer)), false),
+            Ok(None)
+        ));
+        assert_eq!(friend.info.last_updated, Some(newer));
+
+        // Re-fetching the same file is fine too
+        assert!(refresh(&mut friend, fetched(2, Some(newer)), false).is_ok());
+    }
+
+    #[test]
+    fn older_files_are_rejected() {
+        let stored = Utc::now() - Duration::days(1);
+        let older = Utc::now() - Duration::days(2);
+
+        let mut friend = known_friend(Some(stored));
+        assert!(matches!(
+            refresh(&mut friend, fetched(2, Some(older)), false),
+            Err(Rejected::Older {
+                fetched: Some(_),
+                ..
+            })
+        ));
+        assert_eq!(friend.info.last_updated, Some(stored));
+    }
+
+    #[test]
+    fn allow_older_accepts_older_files() {
+        let stored = Utc::now() - Duration::days(1);
+        let older = Utc::now() - Duration::days(2);
+
+        let mut friend = known_friend(Some(stored));
+        assert!(refresh(&mut friend, fetched(2, Some(older)), true).is_ok());
+        assert_eq!(friend.info.last_updated, Some(older));
+    }
+
+    #[test]
+    fn undated_files_are_rejected_after_dated_ones() {
+        let stored = Utc::now() - Duration::days(1);
+
+        let mut friend = known_friend(Some(stored));
+        assert!(matches!(
+            refresh(&mut friend, fetched(2, None), false),
+            Err(Rejected::Older { fetched: None, .. })
+        ));
+
+        // Both undated, as with friends who haven't upgraded yet
+        let mut friend = known_friend(None);
+        friend.format_version = 1;
+        assert!(refresh(&mut friend, fetched(1, None), false).is_ok());
+    }
+
+    #[test]
+    fn files_from_the_future_are_rejected() {
+        let mut friend = known_friend(None);
+
+        let slightly_ahead = Utc::now() + Duration::hours(MAX_FUTURE_HOURS - 1);
+        assert!(refresh(&mut friend, fetched(2, Some(slightly_ahead)), false).is_ok());
+
+        let mut friend = known_friend(None);
+        let future = Utc::now() + Duration::hours(MAX_FUTURE_HOURS + 1);
+        assert!(matches!(
+            refresh(&mut friend, fetched(2, Some(future)), false),
+            Err(Rejected::Future(_))
+        ));
+
+        // Even when older files are allowed
+        assert!(matches!(
+            refresh(&mut friend, fetched(2, Some(future)), true),
+            Err(Rejected::Future(_))
+        ));
+    }
+
+    #[test]
+    fn version_1_is_refused_after_version_2() {
+        let mut friend = known_friend(None);
+        assert!(matches!(
+            refresh(&mut friend, fetched(1, None), true),
+            Err(Rejected::Downgraded {
+                stored: 2,
+                fetched: 1
+            })
+        ));
+
+        // Upgrading is fine, and is remembered
+        let mut friend = known_friend(None);
+        friend.format_version = 1;
+        refresh(&mut friend, fetched(2, Some(Utc::now())), false).unwrap();
+        assert_eq!(friend.format_version, 2);
+    }
 }
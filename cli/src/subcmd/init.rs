@@ -1,25 +1,57 @@
-use std::{collections::HashMap, default::Default, fs::create_dir_all, io::Write};
+use std::{collections::HashMap, default::Default, env, fs::create_dir_all};
 
-use dialoguer::{Confirmation, Input};
 use ed25519_dalek::Keypair;
 use rand::rngs::OsRng;
-use toml::to_string;
+use structopt::StructOpt;
 
 use crate::{
-    consts::{FRIEND_INFO_HEADER, PEER_INFO_HEADER},
     schema::{Friends, Peers, UserInfo},
-    util::{create_private_file, write_user_info},
+    util::{
+        confirm, ensure_interactive, prompt_input, prompt_new_passphrase, save_friends, save_peers,
+        store_keypair, write_user_secrets, NEW_PASSPHRASE_VAR,
+    },
     Error, Result, PATHS,
 };
 
-pub fn init() -> Result<()> {
+#[derive(StructOpt, Debug)]
+#[structopt(rename_all = "kebab-case")]
+pub struct InitOpts {
+    /// The name you want to go by. If given, nothing else will be prompted for,
+    /// and your secret key will only be protected if FRAUTH_NEW_PASSPHRASE is set
+    #[structopt(long = "name")]
+    name: Option<String>,
+
+    /// An identity to associate with yourself, as 'name=id'. Can be given more than once
+    #[structopt(long = "identity", number_of_values = 1, parse(try_from_str = parse_identity))]
+    identities: Vec<(String, String)>,
+
+    /// A public status message
+    #[structopt(long = "status")]
+    status: Option<String>,
+}
+
+fn parse_identity(input: &str) -> Result<(String, String)> {
+    let mut parts = input.splitn(2, '=');
+    match (parts.next(), parts.next()) {
+        (Some(name), Some(id)) if !name.is_empty() && !id.is_empty() => {
+            Ok((name.to_string(), id.to_string()))
+        }
+        _ => Err(Error::Command("identities should look like 'name=id'".into())),
+    }
+}
+
+pub fn init(opts: &InitOpts, yes: bool) -> Result<()> {
     println!("Welcome to frauth!");
 
-    if !Confirmation::new()
-        .with_text("Ready to get started?")
-        .interact()?
-    {
-        return Err(Error::from("Halting init"));
+    let scripted = opts.name.is_some();
+
+    // Fail before any existing files are touched, rather than partway through
+    if !scripted {
+        ensure_interactive("--name")?;
+    }
+
+    if !(yes || scripted || confirm("Ready to get started?", true, "--name")?) {
+        return Err(Error::Command("Halting init".into()));
     }
 
     let already_exists = PATHS.user_info.exists();
@@ -28,32 +60,64 @@ pub fn init() -> Result<()> {
         println!("\nIt looks like you've already initialized frauth.");
         println!("Do you want to re-initialize? THIS WILL ERASE YOUR EXISTING KEYS AND DATA!");
 
-        if !Confirmation::new()
-            .default(false)
-            .with_text("Continue?")
-            .interact()?
-        {
-            return Err(Error::from("Halting init"));
+        if !(yes || confirm("Continue?", false, "--yes")?) {
+            return Err(Error::Command("Halting init".into()));
         }
     }
 
     println!("\nCreating directories...");
 
-    create_dir_all(&PATHS.base_data)?;
-    create_dir_all(&PATHS.base_cache)?;
-
-    // Create files early to prevent late errors
-    let _ = create_private_file(&PATHS.user_info)?;
-    let mut friend_info_file = create_private_file(&PATHS.friend_info)?;
-    let mut peer_info_file = create_private_file(&PATHS.peer_info)?;
+    create_dir_all(&PATHS.base_data).map_err(|e| Error::file(&PATHS.base_data, e))?;
+    create_dir_all(&PATHS.base_cache).map_err(|e| Error::file(&PATHS.base_cache, e))?;
 
     println!("Done.");
 
+    let mut identities: HashMap<String, String> = opts.identities.iter().cloned().collect();
+
+    let (name, status, passphrase) = match opts.name {
+        Some(ref name) => (
+            name.clone(),
+            opts.status.clone(),
+            env::var(NEW_PASSPHRASE_VAR).ok(),
+        ),
+        None => prompt_details(&mut identities, &opts.status)?,
+    };
+
+    let keypair = Keypair::generate(&mut OsRng);
+
+    let mut user_info = UserInfo {
+        name,
+        identities,
+        status,
+        keypair: None,
+        url: None,
+        successions: Vec::new(),
+        encrypted_key: None,
+    };
+
+    store_keypair(&mut user_info, keypair, passphrase.as_deref())?;
+
+    // Previous friends and peers are kept as backups, in case this was a
+    // mistake. The previous key isn't, as it was meant to be erased
+    write_user_secrets(&user_info)?;
+    save_friends(&Friends::default())?;
+    save_peers(&Peers::default())?;
+
+    println!("\nfrauth has been initialized!");
+
+    // TODO: add next steps, like using `frauth me`
+
+    Ok(())
+}
+
+/// Interactively collect the name, identities, status, and passphrase
+fn prompt_details(
+    identities: &mut HashMap<String, String>,
+    status: &Option<String>,
+) -> Result<(String, Option<String>, Option<String>)> {
     println!("\nOkay! We'll get started by collecting some required info.");
 
-    let name = Input::<String>::new()
-        .with_prompt("What name do you want to go by?")
-        .interact()?;
+    let name = prompt_input("What name do you want to go by?", "--name")?;
 
     println!("\nOkay, that's everything that's required. Now let's collect some optional items.");
 
@@ -63,62 +127,48 @@ pub fn init() -> Result<()> {
     println!("\nIdentities have a 'name', like 'twitter', 'email', 'mobile', etc.");
     println!("and an 'id', like 'my_twitter_id', 'me@example.com', or '+4912345678901'.");
 
-    let mut identities: HashMap<String, String> = HashMap::new();
-
-    loop {
-        if !Confirmation::new()
-            .with_text("\nAdd/Update an identity?")
-            .interact()?
-        {
-            break;
-        }
+    if !identities.is_empty() {
+        println!("\nCurrent identities: {:#?}", identities);
+    }
 
-        let id_name = Input::<String>::new()
-            .with_prompt("\nIdentity name")
-            .interact()?;
-        let id_val = Input::<String>::new()
-            .with_prompt(&format!("{} id", id_name))
-            .interact()?;
+    while confirm("\nAdd/Update an identity?", true, "--identity")? {
+        let id_name = prompt_input("\nIdentity name", "--identity")?;
+        let id_val = prompt_input(&format!("{} id", id_name), "--identity")?;
         identities.insert(id_name, id_val);
 
         println!("\nCurrent identities: {:#?}", identities);
     }
 
-    println!("\nWould you like to add a public status message? You can change or add this later as well.");
-
-    let status = if Confirmation::new()
-        .with_text("\nAdd a status?")
-        .interact()?
-    {
-        // TODO: Use an editor instead, limit the character length
-        Some(Input::<String>::new().with_prompt("\nStatus").interact()?)
-    } else {
-        None
+    let status = match status {
+        Some(status) => Some(status.clone()),
+        None => {
+            println!("\nWould you like to add a public status message? You can change or add this later as well.");
+
+            if confirm("\nAdd a status?", true, "--status")? {
+                // TODO: Use an editor instead, limit the character length
+                Some(prompt_input("\nStatus", "--status")?)
+            } else {
+                None
+            }
+        }
     };
 
-    let keypair = Keypair::generate(&mut OsRng);
-
-    let user_info = UserInfo {
-        name,
-        identities,
-        status,
-        keypair,
+    let passphrase = if env::var(NEW_PASSPHRASE_VAR).is_ok() {
+        Some(prompt_new_passphrase()?)
+    } else {
+        println!("\nYour secret key can be protected with a passphrase. You will need to enter it");
+        println!("whenever frauth uses your secret key, such as when publishing or signing.");
+
+        if confirm(
+            "\nProtect your secret key with a passphrase?",
+            true,
+            NEW_PASSPHRASE_VAR,
+        )? {
+            Some(prompt_new_passphrase()?)
+        } else {
+            None
+        }
     };
 
-    let empty_friends = to_string(&Friends::default())?;
-    let empty_peers = to_string(&Peers::default())?;
-
-    write_user_info(&user_info)?;
-
-    friend_info_file.write_all(FRIEND_INFO_HEADER.as_bytes())?;
-    friend_info_file.write_all(empty_friends.as_bytes())?;
-
-    peer_info_file.write_all(PEER_INFO_HEADER.as_bytes())?;
-    peer_info_file.write_all(empty_peers.as_bytes())?;
-
-    println!("\nfrauth has been initialized!");
-
-    // TODO: add next steps, like using `frauth me`
-
-    Ok(())
+    Ok((name, status, passphrase))
 }
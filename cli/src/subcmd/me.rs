@@ -1,10 +1,20 @@
+use std::{fs::OpenOptions, io::Write, path::PathBuf};
+
 use crate::Result;
 
 use base64::encode;
+use ed25519_dalek::Keypair;
+use rand::rngs::OsRng;
 use structopt::StructOpt;
 
 use crate::{
-    util::{load_user_info, write_user_info},
+    output::{print_structured, Format},
+    schema::{KeySuccession, MeView, Revocation},
+    subcmd::friend::check_url,
+    util::{
+        confirm, load_user_info, prompt_new_passphrase, prompt_passphrase, store_keypair,
+        unlock_keypair, write_user_info, write_user_secrets,
+    },
     Error,
 };
 
@@ -18,6 +28,19 @@ pub enum MeOpts {
     },
     /// Edit your configuration
     Edit(EditOpts),
+    /// Replace your keypair with a new one, signing the new key with the old one
+    RotateKey,
+    /// Manage the passphrase protecting your secret key
+    Passphrase(PassphraseOpts),
+    /// Render an announcement that your key should no longer be trusted
+    Revoke {
+        /// Why the key is being revoked, such as 'key was leaked'
+        #[structopt(long = "reason")]
+        reason: Option<String>,
+        /// File to output to. If omitted, the file will be output to stdout
+        #[structopt(short = "o", long = "output")]
+        output: Option<PathBuf>,
+    },
 }
 
 #[derive(StructOpt, Debug)]
@@ -29,6 +52,8 @@ pub enum ViewCmd {
     Status,
     /// Print your public key
     Pubkey,
+    /// Print the URL your frauth file is published at, if set
+    Url,
     /// Print all identities
     Identities,
 }
@@ -43,6 +68,11 @@ pub enum EditOpts {
     },
     /// Edit your status
     Status(StatusOpts),
+    /// Edit the URL your frauth file is published at
+    Url {
+        /// The URL to set
+        url: String,
+    },
     /// Manage your identities
     Identities(IdentitiesOpts),
 }
@@ -58,6 +88,17 @@ pub struct StatusOpts {
     clear: bool,
 }
 
+#[derive(StructOpt, Debug)]
+#[structopt(rename_all = "kebab-case")]
+pub enum PassphraseOpts {
+    /// Protect your secret key with a passphrase
+    Set,
+    /// Change the passphrase protecting your secret key
+    Change,
+    /// Remove the passphrase, storing your secret key in plaintext
+    Remove,
+}
+
 #[derive(StructOpt, Debug)]
 #[structopt(rename_all = "kebab-case")]
 pub enum IdentitiesOpts {
@@ -82,13 +123,17 @@ pub enum IdentitiesOpts {
     },
 }
 
-pub fn me(subcmd: &MeOpts) -> Result<()> {
+pub fn me(subcmd: &MeOpts, yes: bool, format: Format) -> Result<()> {
     match subcmd {
         MeOpts::View { cmd } => match cmd {
-            Some(cmd) => view(cmd),
-            None => view_all(),
+            Some(cmd) if format == Format::Text => view(cmd),
+            None if format == Format::Text => view_all(),
+            cmd => view_structured(cmd.as_ref(), format),
         },
-        MeOpts::Edit(opts) => edit(opts),
+        MeOpts::Edit(opts) => edit(opts, yes),
+        MeOpts::RotateKey => rotate_key(yes),
+        MeOpts::Passphrase(opts) => passphrase(opts),
+        MeOpts::Revoke { reason, output } => revoke(reason, output, yes),
     }
 }
 
@@ -96,8 +141,9 @@ fn view_all() -> Result<()> {
     let user_info = load_user_info()?;
 
     println!("Name:       {}", user_info.name);
-    println!("Status:     {}", user_info.status.unwrap_or_else(|| "<no status is set>".to_string()));
-    println!("Public key: {}", encode(user_info.keypair.public.as_bytes()));
+    println!("Status:     {}", user_info.status.as_deref().unwrap_or("<no status is set>"));
+    println!("Public key: {}", user_info.pubkey());
+    println!("URL:        {}", user_info.url.unwrap_or_else(|| "<no url is set>".to_string()));
 
     println!("\nIdentities:");
     for (name, id) in user_info.identities.iter() {
@@ -107,6 +153,45 @@ fn view_all() -> Result<()> {
     Ok(())
 }
 
+fn view_structured(cmd: Option<&ViewCmd>, format: Format) -> Result<()> {
+    let user_info = load_user_info()?;
+    let pubkey = user_info.pubkey();
+
+    let all = MeView {
+        name: Some(user_info.name),
+        status: user_info.status,
+        pubkey: Some(pubkey),
+        url: user_info.url,
+        identities: Some(user_info.identities),
+    };
+
+    let view = match cmd {
+        None => all,
+        Some(ViewCmd::Name) => MeView {
+            name: all.name,
+            ..MeView::default()
+        },
+        Some(ViewCmd::Status) => MeView {
+            status: all.status,
+            ..MeView::default()
+        },
+        Some(ViewCmd::Pubkey) => MeView {
+            pubkey: all.pubkey,
+            ..MeView::default()
+        },
+        Some(ViewCmd::Url) => MeView {
+            url: all.url,
+            ..MeView::default()
+        },
+        Some(ViewCmd::Identities) => MeView {
+            identities: all.identities,
+            ..MeView::default()
+        },
+    };
+
+    print_structured(format, &view)
+}
+
 fn view(cmd: &ViewCmd) -> Result<()> {
     let user_info = load_user_info()?;
 
@@ -119,8 +204,12 @@ fn view(cmd: &ViewCmd) -> Result<()> {
             None => println!("You haven't set a status!"),
         },
         ViewCmd::Pubkey => {
-            println!("{}", encode(user_info.keypair.public.as_bytes()));
+            println!("{}", user_info.pubkey());
         }
+        ViewCmd::Url => match user_info.url {
+            Some(url) => println!("{}", url),
+            None => println!("You haven't set a URL!"),
+        },
         ViewCmd::Identities => {
             for (name, id) in user_info.identities.iter() {
                 println!("{}: {}", name, id);
@@ -130,7 +219,7 @@ fn view(cmd: &ViewCmd) -> Result<()> {
     Ok(())
 }
 
-fn edit(opts: &EditOpts) -> Result<()> {
+fn edit(opts: &EditOpts, yes: bool) -> Result<()> {
     let mut user_info = load_user_info()?;
 
     match opts {
@@ -138,25 +227,33 @@ fn edit(opts: &EditOpts) -> Result<()> {
             user_info.name = name.clone();
         }
         EditOpts::Status(opts) => {
-            user_info.status = opts.status.clone();
+            user_info.status = if opts.clear {
+                None
+            } else {
+                opts.status.clone()
+            };
+        }
+        EditOpts::Url { url } => {
+            check_url(url, yes)?;
+            user_info.url = Some(url.clone());
         }
         EditOpts::Identities(opts) => match opts {
             IdentitiesOpts::Add { id, name } => {
                 if user_info.identities.contains_key(name) {
-                    return Err(Error::from(format!("identity '{}' already exists, use modify instead", name)));
+                    return Err(Error::Command(format!("identity '{}' already exists, use modify instead", name)));
                 }
                 user_info.identities.insert(name.clone(), id.clone());
             }
             IdentitiesOpts::Modify { id, name } => {
                 if !user_info.identities.contains_key(name) {
-                    return Err(Error::from(format!("identity '{}' does not exist, use add instead", name)));
+                    return Err(Error::Command(format!("identity '{}' does not exist, use add instead", name)));
                 }
                 user_info.identities.insert(name.clone(), id.clone());
             }
             IdentitiesOpts::Remove { name } => {
                 match user_info.identities.remove(name) {
                     Some(_) => {},
-                    None => return Err(Error::from(format!("can't remove identity '{}', identity does not exist!", name))),
+                    None => return Err(Error::Command(format!("can't remove identity '{}', identity does not exist!", name))),
                 };
             }
         },
@@ -166,3 +263,114 @@ fn edit(opts: &EditOpts) -> Result<()> {
 
     Ok(())
 }
+
+fn rotate_key(yes: bool) -> Result<()> {
+    let mut user_info = load_user_info()?;
+
+    println!("This will replace your keypair with a newly generated one.");
+    println!("Your old key will sign a statement naming your new key, so friends");
+    println!("can follow the change when they next update your info.");
+    println!("\nIf your old key has been leaked, use `frauth me revoke` instead!");
+
+    if !(yes || confirm("Continue?", false, "--yes")?) {
+        return Err(Error::Command("Halting key rotation".into()));
+    }
+
+    // Keep the new key protected by the same passphrase as the old one
+    let (old_keypair, passphrase) = match user_info.encrypted_key {
+        Some(ref encrypted) => {
+            let passphrase = prompt_passphrase()?;
+            let keypair = encrypted
+                .open(&passphrase)
+                .map_err(|_| Error::Passphrase)?;
+            (keypair, Some(passphrase))
+        }
+        None => (unlock_keypair(&user_info)?, None),
+    };
+
+    let new_keypair = Keypair::generate(&mut OsRng);
+    let new_pubkey = encode(new_keypair.public.as_bytes());
+
+    user_info
+        .successions
+        .push(KeySuccession::new(&old_keypair, &new_pubkey));
+    store_keypair(&mut user_info, new_keypair, passphrase.as_deref())?;
+
+    write_user_secrets(&user_info)?;
+
+    println!("\nYour new public key is:");
+    println!("  {}", new_pubkey);
+    println!("\nDon't forget to (re)publish with `frauth publish`!");
+
+    Ok(())
+}
+
+fn revoke(reason: &Option<String>, output: &Option<PathBuf>, yes: bool) -> Result<()> {
+    let user_info = load_user_info()?;
+
+    eprintln!("This will create an announcement that your current key should no longer be trusted.");
+    eprintln!("Once you publish it, friends will be warned whenever they use your key.");
+    eprintln!("THIS CAN NOT BE UNDONE!");
+
+    if !(yes || confirm("Continue?", false, "--yes")?) {
+        return Err(Error::Command("Halting revocation".into()));
+    }
+
+    let keypair = unlock_keypair(&user_info)?;
+    let revocation = Revocation::new(&keypair, reason.clone());
+    let contents = revocation.render(&keypair)?;
+
+    if let Some(ref path) = output {
+        let mut opt = OpenOptions::new();
+        opt.write(true);
+        opt.truncate(true);
+        opt.create(true);
+
+        let mut file = opt.open(path).map_err(|e| Error::file(path, e))?;
+        file.write_all(contents.as_bytes())
+            .map_err(|e| Error::file(path, e))?;
+    } else {
+        println!("{}", contents);
+    }
+
+    eprintln!("\nPublish this at your identity URL, in place of your normal frauth file.");
+    eprintln!("You should then start over with `frauth init`, and ask your friends");
+    eprintln!("to remove and re-add you.");
+
+    Ok(())
+}
+
+fn passphrase(opts: &PassphraseOpts) -> Result<()> {
+    let mut user_info = load_user_info()?;
+    let protected = user_info.encrypted_key.is_some();
+
+    match opts {
+        PassphraseOpts::Set if protected => {
+            return Err(Error::Command(
+                "a passphrase is already set, use change instead".into(),
+            ));
+        }
+        PassphraseOpts::Change | PassphraseOpts::Remove if !protected => {
+            return Err(Error::Command("no passphrase is set, use set instead".into()));
+        }
+        _ => {}
+    }
+
+    let keypair = unlock_keypair(&user_info)?;
+
+    match opts {
+        PassphraseOpts::Set | PassphraseOpts::Change => {
+            let passphrase = prompt_new_passphrase()?;
+            store_keypair(&mut user_info, keypair, Some(&passphrase))?;
+            write_user_secrets(&user_info)?;
+            println!("Your secret key is now protected by the new passphrase.");
+        }
+        PassphraseOpts::Remove => {
+            store_keypair(&mut user_info, keypair, None)?;
+            write_user_secrets(&user_info)?;
+            println!("Your secret key is now stored without a passphrase.");
+        }
+    }
+
+    Ok(())
+}
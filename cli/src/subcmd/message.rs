@@ -0,0 +1,116 @@
+use std::io::{stdin, Read};
+
+use frauth_core::SignedMessage;
+use structopt::StructOpt;
+
+use crate::{
+    output::{print_structured, Format},
+    schema::{Signer, VerifiedMessage},
+    util::{load_friends, load_user_info, unlock_keypair, warn_if_revoked},
+    Error, Result,
+};
+
+#[derive(StructOpt, Debug)]
+#[structopt(rename_all = "kebab-case")]
+pub struct SignMessageOpts {
+    /// The URL to include as the signer. If omitted, the URL set with
+    /// `frauth me edit url` will be used
+    #[structopt(long = "url")]
+    url: Option<String>,
+}
+
+pub fn sign_message(opts: &SignMessageOpts) -> Result<()> {
+    let user_info = load_user_info()?;
+
+    let url = match opts.url.as_ref().or(user_info.url.as_ref()) {
+        Some(url) => url,
+        None => {
+            eprintln!("\nA signed message must include the URL of your frauth file.");
+            eprintln!("Set it with `frauth me edit url <url>`, or use `--url <url>`.");
+            return Err(Error::Command("No URL set!".into()));
+        }
+    };
+
+    let mut body = String::new();
+    stdin().read_to_string(&mut body)?;
+
+    let keypair = unlock_keypair(&user_info)?;
+    let message = SignedMessage::sign(url, &body, &keypair);
+
+    print!("{}", message.render());
+
+    Ok(())
+}
+
+pub fn verify_message(format: Format) -> Result<()> {
+    let mut input = String::new();
+    stdin().read_to_string(&mut input)?;
+
+    let message = SignedMessage::parse(&input)?;
+
+    let user_info = load_user_info()?;
+    let friends = load_friends()?;
+
+    let signer = if let Some(friend) = friends.map.get(&message.signer_url) {
+        warn_if_revoked(&message.signer_url, friend);
+
+        // Messages signed before a key rotation are still theirs
+        let pubkey = friend
+            .keys()
+            .find(|key| message.verify(key).is_ok())
+            .unwrap_or(&friend.info.pubkey);
+
+        Signer {
+            url: Some(message.signer_url.clone()),
+            name: friend.info.name.clone(),
+            pubkey: pubkey.to_string(),
+            yourself: false,
+            revoked: friend.revoked.is_some(),
+            rotated: pubkey != friend.info.pubkey,
+        }
+    } else if user_info.url.as_ref() == Some(&message.signer_url) {
+        Signer {
+            url: Some(message.signer_url.clone()),
+            name: user_info.name.clone(),
+            pubkey: user_info.pubkey(),
+            yourself: true,
+            revoked: false,
+            rotated: false,
+        }
+    } else {
+        eprintln!("\nThis message claims to be from '{}',", message.signer_url);
+        eprintln!("but they are not one of your friends.");
+        eprintln!("\nIf you know who this is, add them with `frauth friend add <url>`.");
+        return Err(Error::Trust("Unknown signer!".into()));
+    };
+
+    if message.verify(&signer.pubkey).is_err() {
+        eprintln!("\nThe signature does not match this message!");
+        return Err(Error::Trust("Invalid signature!".into()));
+    }
+
+    if format != Format::Text {
+        return print_structured(
+            format,
+            &VerifiedMessage {
+                body: message.body,
+                signer,
+            },
+        );
+    }
+
+    let name = match friends.map.get(&message.signer_url) {
+        Some(friend) => friend.display_name(),
+        None => &signer.name,
+    };
+
+    eprintln!("Good signature from '{}' ({})", name, message.signer_url);
+    if signer.rotated {
+        eprintln!("\nIt was signed with a key they have since rotated away from:");
+        eprintln!("  {}", signer.pubkey);
+    }
+    eprintln!();
+    print!("{}", message.body);
+
+    Ok(())
+}
@@ -1,4 +1,10 @@
+pub mod crypt;
 pub mod friend;
 pub mod init;
 pub mod me;
+pub mod message;
+pub mod peer;
 pub mod publish;
+pub mod sign;
+pub mod verify;
+pub mod verify_file;
@@ -0,0 +1,510 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet, VecDeque},
+    str::FromStr,
+};
+
+use async_std::task;
+use chrono::Utc;
+use frauth_core::{
+    score::{trust_weight, weighted_score},
+    Document,
+};
+use structopt::StructOpt;
+
+use crate::{
+    output::{print_structured, Format},
+    schema::{CrawlReport, Friends, Hop, PeerChain, PeerChains, PeerInfo, Peers, PublishUserInfo},
+    util::{fetch_documents, load_friends, load_peers, load_user_info, save_peers},
+    Error, Result,
+};
+
+#[derive(StructOpt, Debug)]
+#[structopt(rename_all = "kebab-case")]
+pub enum PeerOpts {
+    /// Follow your friends' friends outward, and cache what is found
+    Crawl {
+        /// How many hops past your friends to follow
+        #[structopt(long = "depth", short = "d", default_value = "2")]
+        depth: usize,
+
+        /// Also follow URLs served over plain HTTP. This is not recommended!
+        #[structopt(long = "allow-http")]
+        allow_http: bool,
+    },
+
+    /// List all cached peers
+    List {
+        /// How to order the list: url, name, or score
+        #[structopt(long = "sort", short = "s", default_value = "url")]
+        sort: SortBy,
+    },
+
+    /// Show every chain of people vouching for a cached peer
+    Why {
+        /// The URL identifying the peer
+        url: String,
+
+        /// How many hops past your friends a chain may take. Peers further
+        /// out than `peer crawl --depth` won't have been cached anyway
+        #[structopt(long = "max-hops", short = "m", default_value = "2")]
+        max_hops: usize,
+    },
+}
+
+#[derive(Debug)]
+pub enum SortBy {
+    Url,
+    Name,
+    Score,
+}
+
+impl FromStr for SortBy {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "url" => Ok(SortBy::Url),
+            "name" => Ok(SortBy::Name),
+            "score" => Ok(SortBy::Score),
+            _ => Err(Error::Command("expected one of: url, name, score".into())),
+        }
+    }
+}
+
+pub fn peer(subcmd: &PeerOpts, format: Format) -> Result<()> {
+    match subcmd {
+        PeerOpts::Crawl { depth, allow_http } => crawl(*depth, *allow_http, format),
+        PeerOpts::List { sort } => list(sort, format),
+        PeerOpts::Why { url, max_hops } => why(url, *max_hops, format),
+    }
+}
+
+/// Someone who lists a URL as one of their friends, and the pubkey they published for it
+struct Referral {
+    referrer: String,
+    pubkey: String,
+}
+
+enum Outcome {
+    Fetched(PublishUserInfo),
+    Revoked,
+    Failed(String),
+}
+
+fn crawl(depth: usize, allow_http: bool, format: Format) -> Result<()> {
+    let user_info = load_user_info()?;
+    let friends = load_friends()?;
+    let old_peers = load_peers()?;
+
+    let my_pubkey = user_info.pubkey();
+
+    // Never crawl ourselves or our friends, we already know about them
+    let mut seen: HashSet<String> = friends.map.keys().cloned().collect();
+    if let Some(url) = user_info.url {
+        seen.insert(url);
+    }
+
+    let mut frontier = BTreeMap::new();
+    for (uri, friend) in friends.map.iter() {
+        if friend.revoked.is_none() {
+            add_referrals(&mut frontier, uri, &friend.info);
+        }
+    }
+
+    let mut peers = Peers::default();
+    let mut problems = Vec::new();
+
+    for level in 1..=depth {
+        frontier.retain(|url, _| {
+            if seen.contains(url) {
+                false
+            } else if !is_allowed_url(url, allow_http) {
+                problems.push(format!("{}: not served over https, skipping", url));
+                seen.insert(url.clone());
+                false
+            } else {
+                true
+            }
+        });
+
+        if frontier.is_empty() {
+            break;
+        }
+
+        eprintln!("Crawling {} peer(s) at depth {}...", frontier.len(), level);
+
+        let results = task::block_on(fetch_all(frontier.keys().cloned().collect()));
+        let mut next = BTreeMap::new();
+
+        for (url, outcome) in results {
+            seen.insert(url.clone());
+            let referrals = &frontier[&url];
+
+            match outcome {
+                Outcome::Fetched(info) => {
+                    for referral in referrals.iter().filter(|r| r.pubkey != info.pubkey) {
+                        problems.push(format!(
+                            "{}: public key does not match the one published by {}",
+                            url, referral.referrer
+                        ));
+                    }
+
+                    if info.pubkey == my_pubkey || referrals.iter().all(|r| r.pubkey != info.pubkey)
+                    {
+                        continue;
+                    }
+
+                    if let Some(expires) = info.expires.filter(|_| info.is_expired()) {
+                        problems.push(format!(
+                            "{}: file expired on {}, it may be stale",
+                            url, expires
+                        ));
+                    }
+
+                    add_referrals(&mut next, &url, &info);
+                    peers.map.insert(
+                        url,
+                        PeerInfo {
+                            last_updated: Utc::now(),
+                            score: 0.0,
+                            info,
+                        },
+                    );
+                }
+                Outcome::Revoked => {
+                    problems.push(format!("{}: has revoked their public key", url));
+                }
+                Outcome::Failed(reason) => {
+                    problems.push(format!("{}: {}", url, reason));
+
+                    // Keep what we knew before, as long as it still matches a referral
+                    if let Some(old) = old_peers.map.get(&url) {
+                        if referrals.iter().any(|r| r.pubkey == old.info.pubkey) {
+                            add_referrals(&mut next, &url, &old.info);
+                            peers.map.insert(url, old.clone());
+                        }
+                    }
+                }
+            }
+        }
+
+        frontier = next;
+    }
+
+    score_peers(&friends, &mut peers);
+    save_peers(&peers)?;
+
+    if format != Format::Text {
+        return print_structured(
+            format,
+            &CrawlReport {
+                cached: peers.map.len(),
+                problems,
+            },
+        );
+    }
+
+    println!("\nCached {} peer(s).", peers.map.len());
+
+    if !problems.is_empty() {
+        println!("\nProblems found while crawling:");
+        for problem in problems.iter() {
+            println!("  - {}", problem);
+        }
+    }
+
+    Ok(())
+}
+
+fn list(sort: &SortBy, format: Format) -> Result<()> {
+    let peers = load_peers()?;
+
+    // Always in URL order, as tooling can sort however it likes
+    if format != Format::Text {
+        return print_structured(format, &peers);
+    }
+    let mut peers = peers.map.iter().collect::<Vec<_>>();
+
+    match sort {
+        // Already in URL order
+        SortBy::Url => {}
+        SortBy::Name => peers.sort_by(|a, b| a.1.info.name.cmp(&b.1.info.name)),
+        SortBy::Score => peers.sort_by(|a, b| b.1.score.total_cmp(&a.1.score)),
+    }
+
+    for (uri, peer) in peers {
+        println!("{:.3} {} - {}", peer.score, peer.info.name, uri);
+    }
+    Ok(())
+}
+
+fn why(url: &str, max_hops: usize, format: Format) -> Result<()> {
+    let friends = load_friends()?;
+    let peers = load_peers()?;
+
+    if let Some(friend) = friends.map.get(url) {
+        if format != Format::Text {
+            let hop = Hop {
+                url: url.to_string(),
+                name: friend.info.name.clone(),
+                pubkey: friend.info.pubkey.clone(),
+                trust: friend.trust,
+                mismatch: false,
+            };
+            return print_structured(
+                format,
+                &PeerChains {
+                    score: 1.0,
+                    chains: vec![PeerChain { hops: vec![hop] }],
+                },
+            );
+        }
+
+        println!("'{}' is your friend, you verified them directly!", url);
+        return Ok(());
+    }
+
+    let target = match peers.map.get(url) {
+        Some(target) => target,
+        None => {
+            eprintln!("\nWe don't know about '{}' yet!", url);
+            eprintln!("\nRun `frauth peer crawl` to update the peer cache.");
+            return Err(Error::Command("Peer not known!".into()));
+        }
+    };
+
+    let mut chains = Vec::new();
+
+    for (uri, friend) in friends.map.iter() {
+        if friend.revoked.is_some() {
+            continue;
+        }
+
+        let mut path = vec![Hop {
+            url: uri.clone(),
+            name: friend.info.name.clone(),
+            pubkey: friend.info.pubkey.clone(),
+            trust: friend.trust,
+            mismatch: false,
+        }];
+        walk(
+            url,
+            &friend.info,
+            &friends,
+            &peers,
+            max_hops,
+            &mut path,
+            &mut chains,
+        );
+    }
+
+    chains.sort_by_key(|c| c.hops.len());
+
+    if format != Format::Text {
+        return print_structured(
+            format,
+            &PeerChains {
+                score: target.score,
+                chains,
+            },
+        );
+    }
+
+    if chains.is_empty() {
+        println!(
+            "Nobody vouches for '{}' within {} hop(s) of your friends.",
+            url, max_hops
+        );
+        println!("\nTry a higher `--max-hops`, or run `frauth peer crawl` to update the");
+        println!("peer cache.");
+        return Ok(());
+    }
+
+    for (i, chain) in chains.iter().enumerate() {
+        println!("Chain {}:", i + 1);
+        println!("  you");
+
+        let mut source = "verified by you".to_string();
+        for hop in chain.hops.iter() {
+            let name = match friends.map.get(&hop.url) {
+                Some(friend) => friend.display_name(),
+                None => &hop.name,
+            };
+
+            println!("  -> {} - {}", name, hop.url);
+            if let Some(trust) = hop.trust {
+                source = format!("{}, trust: {}", source, trust);
+            }
+            if hop.mismatch {
+                println!("     pubkey: {} ({}, DOES NOT MATCH)", hop.pubkey, source);
+            } else {
+                println!("     pubkey: {} ({})", hop.pubkey, source);
+            }
+            source = format!("published by {}", name);
+        }
+        println!();
+    }
+
+    println!("Score: {:.3}", target.score);
+
+    Ok(())
+}
+
+/// Collect every chain from the end of `path` to `target`, without visiting anyone twice
+///
+/// Chains only pass through peers whose cached public key matches the one
+/// published by the previous hop, as we don't know who they vouch for otherwise.
+/// The final hop to `target` is always included, so mismatches can be shown.
+///
+/// `path` starts with one of your friends, so chains end at most `max_hops`
+/// hops past them. Without a limit, the number of chains can grow
+/// exponentially with the size of the peer cache.
+fn walk(
+    target: &str,
+    info: &PublishUserInfo,
+    friends: &Friends,
+    peers: &Peers,
+    max_hops: usize,
+    path: &mut Vec<Hop>,
+    chains: &mut Vec<PeerChain>,
+) {
+    if path.len() > max_hops {
+        return;
+    }
+
+    for friend in info.friends.iter() {
+        if friend.uri == target {
+            let target_info = &peers.map[target].info;
+            let mut hops = path.clone();
+            hops.push(Hop {
+                url: friend.uri.clone(),
+                name: target_info.name.clone(),
+                pubkey: friend.pubkey.clone(),
+                trust: friend.trust,
+                mismatch: friend.pubkey != target_info.pubkey,
+            });
+            chains.push(PeerChain { hops });
+            continue;
+        }
+
+        // Friends start their own chains
+        if friends.map.contains_key(&friend.uri) || path.iter().any(|h| h.url == friend.uri) {
+            continue;
+        }
+
+        let peer = match peers.map.get(&friend.uri) {
+            Some(peer) if peer.info.pubkey == friend.pubkey => peer,
+            _ => continue,
+        };
+
+        path.push(Hop {
+            url: friend.uri.clone(),
+            name: peer.info.name.clone(),
+            pubkey: friend.pubkey.clone(),
+            trust: friend.trust,
+            mismatch: false,
+        });
+        walk(target, &peer.info, friends, peers, max_hops, path, chains);
+        path.pop();
+    }
+}
+
+/// Score every cached peer, based on who vouches for them
+///
+/// Friends are at depth 1, and a peer is at one more than the shallowest
+/// depth of anyone vouching for them. A voucher only counts if the public
+/// key they published for the peer matches the one we cached.
+///
+/// Each voucher counts for the weight of the trust they published in the
+/// peer. Vouchers who are our friends are also weighted by our own trust in them.
+fn score_peers(friends: &Friends, peers: &mut Peers) {
+    let mut depths: HashMap<&str, usize> = HashMap::new();
+    let mut queue = VecDeque::new();
+
+    for (uri, friend) in friends.map.iter() {
+        if friend.revoked.is_none() {
+            depths.insert(uri, 1);
+            queue.push_back((uri.as_str(), &friend.info, trust_weight(friend.trust)));
+        }
+    }
+
+    let mut weights: HashMap<&str, Vec<f64>> = HashMap::new();
+
+    while let Some((uri, info, voucher_weight)) = queue.pop_front() {
+        let depth = depths[uri];
+
+        // Someone listing a peer twice only counts once, at their highest trust
+        let mut vouched: HashMap<&str, f64> = HashMap::new();
+        for f in info.friends.iter() {
+            match peers.map.get(&f.uri) {
+                Some(peer) if peer.info.pubkey == f.pubkey => {}
+                _ => continue,
+            }
+            let weight = voucher_weight * trust_weight(f.trust);
+            let entry = vouched.entry(f.uri.as_str()).or_insert(weight);
+            *entry = entry.max(weight);
+        }
+
+        for (peer_uri, weight) in vouched {
+            let peer_weights = weights.entry(peer_uri).or_default();
+            if peer_weights.len() <= depth {
+                peer_weights.resize(depth + 1, 0.0);
+            }
+            peer_weights[depth] += weight;
+
+            if !depths.contains_key(peer_uri) {
+                depths.insert(peer_uri, depth + 1);
+                queue.push_back((peer_uri, &peers.map[peer_uri].info, 1.0));
+            }
+        }
+    }
+
+    let scores = weights
+        .into_iter()
+        .map(|(uri, weights)| (uri.to_string(), weighted_score(&weights)))
+        .collect::<Vec<_>>();
+
+    for peer in peers.map.values_mut() {
+        peer.score = 0.0;
+    }
+    for (uri, score) in scores {
+        if let Some(peer) = peers.map.get_mut(&uri) {
+            peer.score = score;
+        }
+    }
+}
+
+fn is_allowed_url(url: &str, allow_http: bool) -> bool {
+    url.starts_with("https://") || (allow_http && url.starts_with("http://"))
+}
+
+fn add_referrals(
+    frontier: &mut BTreeMap<String, Vec<Referral>>,
+    referrer: &str,
+    info: &PublishUserInfo,
+) {
+    for friend in info.friends.iter() {
+        frontier
+            .entry(friend.uri.clone())
+            .or_default()
+            .push(Referral {
+                referrer: referrer.to_string(),
+                pubkey: friend.pubkey.clone(),
+            });
+    }
+}
+
+async fn fetch_all(urls: Vec<String>) -> Vec<(String, Outcome)> {
+    fetch_documents(urls)
+        .await
+        .into_iter()
+        .map(|(url, document)| {
+            let outcome = match document.map(|fetched| fetched.document) {
+                Ok(Document::Identity(info)) => Outcome::Fetched(info),
+                Ok(Document::Revocation(_)) => Outcome::Revoked,
+                Err(e) => Outcome::Failed(e.to_string()),
+            };
+            (url, outcome)
+        })
+        .collect()
+}
@@ -1,35 +1,88 @@
 use std::{
+    collections::BTreeMap,
     fs::OpenOptions,
     io::Write,
     path::PathBuf,
 };
 
+use async_std::task;
 use base64::encode;
-use chrono::Utc;
+use chrono::{Duration, Utc};
+use frauth_core::Document;
 use structopt::StructOpt;
-use toml::to_string;
 
 use crate::{
-    schema::{PublishFriend, PublishUserInfo, UserInfo},
-    util::{load_user_info, load_friends},
-    Result,
+    schema::{PublishFriend, PublishUserInfo, Trust, UserInfo},
+    util::{fetch_document, load_user_info, load_friends, unlock_keypair, warn_if_revoked},
+    Error, Result,
 };
 
-pub const HEADER_TOP: &str = "FRAUTH-CONTENTS\n";
-pub const HEADER_SIGNATURE: &str = "FRAUTH-SIGNATURE\n";
-pub const HEADER_END_OF_FILE: &str = "FRAUTH-ENDOFFILE\n";
-
 #[derive(StructOpt, Debug)]
 #[structopt(rename_all = "kebab-case")]
 pub struct PublishOpts {
     /// File to output to. If omitted, the file will be output to stdout
     #[structopt(short = "o", long = "output")]
     output: Option<PathBuf>,
+
+    /// Instead of rendering, check that the file published at this URL is up
+    /// to date. If no URL is given, the URL set with `frauth me edit url` is used
+    #[structopt(long = "check", value_name = "url", conflicts_with = "output")]
+    check: Option<Option<String>>,
+
+    /// How long the file stays valid, such as '90d', '12w' or '48h'. Friends
+    /// will treat it as stale after this. If omitted, the file never expires
+    #[structopt(long = "valid-for", parse(try_from_str = parse_duration), conflicts_with = "check")]
+    valid_for: Option<Duration>,
+
+    /// Include how sure you are of each public friend's key, as set with
+    /// `frauth friend edit --trust`. Peers use this to weight their scores
+    #[structopt(long = "with-trust")]
+    with_trust: bool,
+}
+
+/// Warn about files that expire within this many days
+const EXPIRY_WARNING_DAYS: i64 = 14;
+
+/// The longest a file may stay valid for, in days. Re-publishing at least
+/// this often is no burden, and it keeps dates well within range
+const MAX_VALID_DAYS: i64 = 100 * 365;
+
+fn parse_duration(input: &str) -> Result<Duration> {
+    let invalid = || Error::Command("durations should look like '90d', '12w' or '48h'".into());
+
+    let unit_start = input.char_indices().last().map(|(i, _)| i).unwrap_or(0);
+    let (count, unit) = input.split_at(unit_start);
+    let count = count.parse::<i64>().map_err(|_| invalid())?;
+
+    let hours_per_unit = match unit {
+        "h" => 1,
+        "d" => 24,
+        "w" => 24 * 7,
+        _ => return Err(invalid()),
+    };
+
+    if count <= 0 {
+        return Err(invalid());
+    }
+    if count > MAX_VALID_DAYS * 24 / hours_per_unit {
+        return Err(Error::Command(format!(
+            "files can be valid for at most {} days",
+            MAX_VALID_DAYS
+        )));
+    }
+
+    Ok(Duration::hours(count * hours_per_unit))
 }
 
 pub fn publish(opts: &PublishOpts) -> Result<()> {
     let user_info = load_user_info()?;
-    let contents = render_to_string(user_info)?;
+
+    if let Some(ref url) = opts.check {
+        let url = url.as_ref().or(user_info.url.as_ref()).cloned();
+        return check(url, user_info, opts.with_trust);
+    }
+
+    let contents = render_to_string(user_info, opts.valid_for, opts.with_trust)?;
 
     if let Some(ref path) = opts.output {
         let mut opt = OpenOptions::new();
@@ -37,8 +90,9 @@ pub fn publish(opts: &PublishOpts) -> Result<()> {
         opt.truncate(true);
         opt.create(true);
 
-        let mut file = opt.open(path)?;
-        file.write_all(contents.as_bytes())?;
+        let mut file = opt.open(path).map_err(|e| Error::file(path, e))?;
+        file.write_all(contents.as_bytes())
+            .map_err(|e| Error::file(path, e))?;
     } else {
         println!("{}", contents);
     }
@@ -46,16 +100,56 @@ pub fn publish(opts: &PublishOpts) -> Result<()> {
     Ok(())
 }
 
-fn render_to_string(mut user_info: UserInfo) -> Result<String> {
+fn render_to_string(
+    user_info: UserInfo,
+    valid_for: Option<Duration>,
+    with_trust: bool,
+) -> Result<String> {
+    let keypair = unlock_keypair(&user_info)?;
+    let mut pub_info = to_pub_info(user_info, encode(keypair.public.as_bytes()), with_trust)?;
+
+    if let Some(valid_for) = valid_for {
+        let expires = Utc::now()
+            .checked_add_signed(valid_for)
+            .ok_or_else(|| Error::Command("the expiry date is out of range".into()))?;
+        pub_info.expires = Some(expires);
+
+        eprintln!("\nThis file expires on {}.", expires);
+        if valid_for < Duration::days(EXPIRY_WARNING_DAYS) {
+            eprintln!("WARNING! That is very soon, so remember to re-publish before then!");
+        } else {
+            eprintln!("Remember to re-publish before then!");
+        }
+    } else {
+        // Publishing doesn't fetch the hosted file, so we can't tell if it is about to expire
+        eprintln!("\nThis file never expires. Use `--valid-for` to have friends treat it as");
+        eprintln!("stale after a while, and `frauth publish --check` to see if the file");
+        eprintln!("hosted at your URL has expired.");
+    }
+
+    Ok(frauth_core::encode(&pub_info, &keypair)?)
+}
+
+/// Gather everything that should be published for `user_info`
+fn to_pub_info(
+    mut user_info: UserInfo,
+    pubkey: String,
+    with_trust: bool,
+) -> Result<PublishUserInfo> {
     let friends = load_friends()?;
     let pub_friends = friends
         .map
         .iter()
         .filter_map(|(uri, friend)| {
-            if friend.public {
+            if friend.public && friend.revoked.is_some() {
+                warn_if_revoked(uri, friend);
+                eprintln!("Leaving '{}' out of your published friends.", uri);
+                None
+            } else if friend.public {
                 Some(PublishFriend {
                     uri: uri.to_string(),
                     pubkey: friend.info.pubkey.to_string(),
+                    trust: friend.trust.filter(|_| with_trust),
                 })
             } else {
                 None
@@ -63,25 +157,330 @@ fn render_to_string(mut user_info: UserInfo) -> Result<String> {
         })
         .collect();
 
-    let pub_info = PublishUserInfo {
+    Ok(PublishUserInfo {
         name: user_info.name,
         status: user_info.status,
-        pubkey: encode(user_info.keypair.public.as_bytes()),
+        pubkey,
         last_updated: Some(Utc::now()),
+        expires: None,
         identities: user_info.identities.drain().collect(),
         friends: pub_friends,
+        successions: user_info.successions,
+    })
+}
+
+fn check(url: Option<String>, user_info: UserInfo, with_trust: bool) -> Result<()> {
+    let url = match url {
+        Some(url) => url,
+        None => {
+            eprintln!("\nNo URL was given, and you haven't set one.");
+            eprintln!("Set it with `frauth me edit url <url>`, or use `--check <url>`.");
+            return Err(Error::Command("No URL set!".into()));
+        }
+    };
+
+    let pubkey = user_info.pubkey();
+    let current = to_pub_info(user_info, pubkey.clone(), with_trust)?;
+
+    let hosted = match task::block_on(fetch_document(&url))? {
+        Document::Identity(pub_info) => pub_info,
+        Document::Revocation(revocation) => {
+            eprintln!(
+                "\n`{}` is a revocation of your key, made on {}.",
+                url, revocation.revoked_at
+            );
+            return Err(Error::Trust("Identity revoked!".into()));
+        }
     };
 
-    let toml_contents = to_string(&pub_info)?;
-    let sig = user_info.keypair.sign(toml_contents.as_bytes());
+    if hosted.pubkey != pubkey {
+        eprintln!("\nThe file at `{}` is signed by a different public key:", url);
+        eprintln!("  yours:  {}", pubkey);
+        eprintln!("  hosted: {}", hosted.pubkey);
+        return Err(Error::Trust("Public key mismatch!".into()));
+    }
+
+    let mut differences = differences(&hosted, &current);
 
-    let mut contents = String::new();
-    contents += HEADER_TOP;
-    contents += &toml_contents;
-    contents += HEADER_SIGNATURE;
-    contents += &encode(&sig.to_bytes()[..]);
-    contents += "\n";
-    contents += HEADER_END_OF_FILE;
+    if let Some(expires) = hosted.expires {
+        if hosted.is_expired() {
+            differences.push(format!("it expired on {}", expires));
+        } else if expires < Utc::now() + Duration::days(EXPIRY_WARNING_DAYS) {
+            eprintln!("\nWARNING! The file at `{}` expires soon, on {}.", url, expires);
+            eprintln!("Re-publish before then, or friends will treat it as stale.");
+        }
+    }
+
+    if differences.is_empty() {
+        println!("The file at `{}` is up to date.", url);
+        return Ok(());
+    }
 
-    Ok(contents)
+    match hosted.last_updated {
+        Some(last_updated) => println!("The file at `{}` was published on {},", url, last_updated),
+        None => println!("The file at `{}`", url),
+    }
+    println!("and is out of date:");
+    for difference in differences.iter() {
+        println!("  - {}", difference);
+    }
+    if with_trust {
+        println!("\nRe-publish with `frauth publish --with-trust`!");
+    } else {
+        println!("\nRe-publish with `frauth publish`!");
+    }
+
+    Err(Error::Command("Published file is out of date!".into()))
+}
+
+/// Describe everything that differs between the hosted and current contents,
+/// other than when they were published
+fn differences(hosted: &PublishUserInfo, current: &PublishUserInfo) -> Vec<String> {
+    let mut out = Vec::new();
+
+    if hosted.name != current.name {
+        out.push(format!("name is '{}', now '{}'", hosted.name, current.name));
+    }
+
+    if hosted.status != current.status {
+        let show = |status: &Option<String>| {
+            status
+                .as_ref()
+                .map(|s| format!("'{}'", s))
+                .unwrap_or_else(|| "unset".to_string())
+        };
+        out.push(format!(
+            "status is {}, now {}",
+            show(&hosted.status),
+            show(&current.status)
+        ));
+    }
+
+    compare_maps(
+        &mut out,
+        "identity",
+        &hosted.identities,
+        &current.identities,
+    );
+
+    let friends = |info: &PublishUserInfo| -> BTreeMap<String, String> {
+        info.friends
+            .iter()
+            .map(|f| (f.uri.clone(), f.pubkey.clone()))
+            .collect()
+    };
+    compare_maps(&mut out, "friend", &friends(hosted), &friends(current));
+
+    for now in current.friends.iter() {
+        let was = match hosted.friends.iter().find(|f| f.uri == now.uri) {
+            Some(was) if was.trust != now.trust => was,
+            _ => continue,
+        };
+        let show = |trust: Option<Trust>| {
+            trust
+                .map(|t| t.to_string())
+                .unwrap_or_else(|| "unset".to_string())
+        };
+        out.push(format!(
+            "trust in friend '{}' is {}, now {}",
+            now.uri,
+            show(was.trust),
+            show(now.trust)
+        ));
+    }
+
+    if hosted.successions != current.successions {
+        out.push("key rotations are missing or different".to_string());
+    }
+
+    out
+}
+
+fn compare_maps(
+    out: &mut Vec<String>,
+    what: &str,
+    hosted: &BTreeMap<String, String>,
+    current: &BTreeMap<String, String>,
+) {
+    for (key, value) in hosted.iter() {
+        match current.get(key) {
+            None => out.push(format!("{} '{}' should be removed", what, key)),
+            Some(now) if now != value => {
+                out.push(format!("{} '{}' is '{}', now '{}'", what, key, value, now))
+            }
+            Some(_) => {}
+        }
+    }
+
+    for key in current.keys().filter(|key| !hosted.contains_key(*key)) {
+        out.push(format!("{} '{}' is missing", what, key));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schema::KeySuccession;
+
+    const ADA: &str = "https://example.com/ada.frauth";
+
+    fn info() -> PublishUserInfo {
+        PublishUserInfo {
+            name: "Grace".into(),
+            status: Some("compiling".into()),
+            pubkey: "11qYAYKxCrfVS/7TyWQHOg7hcvPapiMlrwIaaPcHURo=".into(),
+            last_updated: Some(Utc::now()),
+            expires: None,
+            friends: vec![PublishFriend {
+                uri: ADA.into(),
+                pubkey: "3KuJFZNt+KhMrTxGVvKb5MIGldbzD8OT4KpQT7mRWUQ=".into(),
+                trust: Some(Trust::High),
+            }],
+            identities: vec![("github".to_string(), "grace".to_string())]
+                .into_iter()
+                .collect(),
+            successions: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn durations() {
+        assert_eq!(parse_duration("90d").unwrap(), Duration::days(90));
+        assert_eq!(parse_duration("12w").unwrap(), Duration::weeks(12));
+        assert_eq!(parse_duration("48h").unwrap(), Duration::hours(48));
+    }
+
+    #[test]
+    fn durations_must_be_positive() {
+        assert!(matches!(parse_duration("0d"), Err(Error::Command(_))));
+        assert!(matches!(parse_duration("-1d"), Err(Error::Command(_))));
+    }
+
+    #[test]
+    fn durations_need_a_count_and_unit() {
+        for input in &["", "d", "90", "90m", "90 d", "ninety d", "1.5w"] {
+            assert!(
+                matches!(parse_duration(input), Err(Error::Command(_))),
+                "{:?} should be rejected",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn durations_are_capped() {
+        let longest = format!("{}d", MAX_VALID_DAYS);
+        assert_eq!(
+            parse_duration(&longest).unwrap(),
+            Duration::days(MAX_VALID_DAYS)
+        );
+
+        for input in &[
+            format!("{}d", MAX_VALID_DAYS + 1),
+            format!("{}h", MAX_VALID_DAYS * 24 + 1),
+            format!("{}w", MAX_VALID_DAYS / 7 + 1),
+            format!("{}d", i64::MAX),
+        ] {
+            assert!(
+                matches!(parse_duration(input), Err(Error::Command(_))),
+                "{:?} should be rejected",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn identical_contents_have_no_differences() {
+        let hosted = info();
+        let mut current = info();
+
+        // Only the publishing date differs
+        current.last_updated = Some(Utc::now() + Duration::hours(1));
+        assert!(differences(&hosted, &current).is_empty());
+    }
+
+    #[test]
+    fn name_and_status_differences() {
+        let mut current = info();
+        current.name = "Grace Hopper".into();
+        current.status = None;
+
+        assert_eq!(
+            differences(&info(), &current),
+            vec![
+                "name is 'Grace', now 'Grace Hopper'",
+                "status is 'compiling', now unset",
+            ]
+        );
+    }
+
+    #[test]
+    fn identity_differences() {
+        let mut current = info();
+        current.identities.insert("github".into(), "ghopper".into());
+        current.identities.insert("gitlab".into(), "grace".into());
+        assert_eq!(
+            differences(&info(), &current),
+            vec![
+                "identity 'github' is 'grace', now 'ghopper'",
+                "identity 'gitlab' is missing",
+            ]
+        );
+
+        current.identities.clear();
+        assert_eq!(
+            differences(&info(), &current),
+            vec!["identity 'github' should be removed"]
+        );
+    }
+
+    #[test]
+    fn friend_differences() {
+        let mut current = info();
+        let hosted_key = current.friends[0].pubkey.clone();
+        current.friends[0].pubkey = "new key".into();
+        assert_eq!(
+            differences(&info(), &current),
+            vec![format!(
+                "friend '{}' is '{}', now 'new key'",
+                ADA, hosted_key
+            )]
+        );
+
+        current.friends.clear();
+        assert_eq!(
+            differences(&info(), &current),
+            vec![format!("friend '{}' should be removed", ADA)]
+        );
+        assert_eq!(
+            differences(&current, &info()),
+            vec![format!("friend '{}' is missing", ADA)]
+        );
+    }
+
+    #[test]
+    fn trust_differences() {
+        let mut current = info();
+        current.friends[0].trust = None;
+
+        assert_eq!(
+            differences(&info(), &current),
+            vec![format!("trust in friend '{}' is high, now unset", ADA)]
+        );
+    }
+
+    #[test]
+    fn succession_differences() {
+        let mut current = info();
+        current.successions.push(KeySuccession {
+            old: "3KuJFZNt+KhMrTxGVvKb5MIGldbzD8OT4KpQT7mRWUQ=".into(),
+            new: current.pubkey.clone(),
+            signature: "not checked here".into(),
+        });
+
+        assert_eq!(
+            differences(&info(), &current),
+            vec!["key rotations are missing or different"]
+        );
+    }
 }
@@ -0,0 +1,54 @@
+use std::{
+    fs::{read, OpenOptions},
+    io::Write,
+    path::PathBuf,
+};
+
+use frauth_core::DetachedSignature;
+use structopt::StructOpt;
+
+use crate::{
+    util::{load_user_info, unlock_keypair},
+    Error, Result,
+};
+
+#[derive(StructOpt, Debug)]
+#[structopt(rename_all = "kebab-case")]
+pub struct SignOpts {
+    /// The file to sign
+    file: PathBuf,
+
+    /// File to write the signature to. If omitted, `<file>.sig` will be used
+    #[structopt(short = "o", long = "output")]
+    output: Option<PathBuf>,
+}
+
+pub fn sign(opts: &SignOpts) -> Result<()> {
+    let user_info = load_user_info()?;
+    let data = read(&opts.file).map_err(|e| Error::file(&opts.file, e))?;
+
+    let keypair = unlock_keypair(&user_info)?;
+    let signature = DetachedSignature::sign(&data, &keypair);
+
+    let output = match opts.output {
+        Some(ref path) => path.clone(),
+        None => {
+            let mut path = opts.file.clone().into_os_string();
+            path.push(".sig");
+            path.into()
+        }
+    };
+
+    let mut opt = OpenOptions::new();
+    opt.write(true);
+    opt.truncate(true);
+    opt.create(true);
+
+    let mut file = opt.open(&output).map_err(|e| Error::file(&output, e))?;
+    file.write_all(signature.render().as_bytes())
+        .map_err(|e| Error::file(&output, e))?;
+
+    println!("Wrote signature to '{}'.", output.display());
+
+    Ok(())
+}
@@ -0,0 +1,94 @@
+use std::{
+    fs::{read, read_to_string},
+    path::PathBuf,
+};
+
+use frauth_core::DetachedSignature;
+use structopt::StructOpt;
+
+use crate::{
+    output::{print_structured, Format},
+    schema::Signer,
+    util::{load_friends, load_user_info, warn_if_revoked},
+    Error, Result,
+};
+
+#[derive(StructOpt, Debug)]
+#[structopt(rename_all = "kebab-case")]
+pub struct VerifyOpts {
+    /// The file that was signed
+    file: PathBuf,
+
+    /// The detached signature of the file
+    signature: PathBuf,
+}
+
+pub fn verify(opts: &VerifyOpts, format: Format) -> Result<()> {
+    let data = read(&opts.file).map_err(|e| Error::file(&opts.file, e))?;
+    let signature = read_to_string(&opts.signature).map_err(|e| Error::file(&opts.signature, e))?;
+    let signature = DetachedSignature::parse(&signature)?;
+
+    let friends = load_friends()?;
+
+    for (uri, friend) in friends.map.iter() {
+        let pubkey = match friend
+            .keys()
+            .find(|key| signature.verify(&data, key).is_ok())
+        {
+            Some(pubkey) => pubkey,
+            None => continue,
+        };
+        let rotated = pubkey != friend.info.pubkey;
+
+        warn_if_revoked(uri, friend);
+        if format != Format::Text {
+            return print_structured(
+                format,
+                &Signer {
+                    url: Some(uri.clone()),
+                    name: friend.info.name.clone(),
+                    pubkey: pubkey.to_string(),
+                    yourself: false,
+                    revoked: friend.revoked.is_some(),
+                    rotated,
+                },
+            );
+        }
+        println!("Good signature from '{}' ({})", friend.display_name(), uri);
+        if rotated {
+            println!("\nIt was signed with a key they have since rotated away from:");
+            println!("  {}", pubkey);
+        }
+        return Ok(());
+    }
+
+    let user_info = load_user_info()?;
+    let my_pubkey = user_info.pubkey();
+    if signature.verify(&data, &my_pubkey).is_ok() {
+        if format != Format::Text {
+            return print_structured(
+                format,
+                &Signer {
+                    url: user_info.url,
+                    name: user_info.name,
+                    pubkey: my_pubkey,
+                    yourself: true,
+                    revoked: false,
+                    rotated: false,
+                },
+            );
+        }
+        println!("Good signature from yourself");
+        return Ok(());
+    }
+
+    if signature.verify(&data, &signature.signer).is_ok() {
+        eprintln!("\nThe signature is valid, but was made by an unknown key:");
+        eprintln!("  {}", signature.signer);
+        eprintln!("\nIf you know who this is, add them with `frauth friend add <url>`.");
+        return Err(Error::Trust("Unknown signer!".into()));
+    }
+
+    eprintln!("\nThe signature does not match this file!");
+    Err(Error::Trust("Invalid signature!".into()))
+}
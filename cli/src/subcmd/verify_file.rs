@@ -0,0 +1,120 @@
+use std::{
+    fs::read_to_string,
+    io::{stdin, Read},
+};
+
+use async_std::task;
+use frauth_core::{follow_successions, Document};
+use structopt::StructOpt;
+
+use crate::{
+    output::{print_structured, Format},
+    schema::VerifiedFile,
+    util::{decode_versioned, fetch_versioned},
+    Error, Result,
+};
+
+#[derive(StructOpt, Debug)]
+#[structopt(rename_all = "kebab-case")]
+pub struct VerifyFileOpts {
+    /// A path to the frauth file, a URL it is published at, or '-' to read it from stdin
+    source: String,
+
+    /// The public key the file is expected to be signed by. A newer key is
+    /// also accepted, if the expected key signed its succession
+    #[structopt(long = "pubkey")]
+    pubkey: Option<String>,
+}
+
+pub fn verify_file(opts: &VerifyFileOpts, format: Format) -> Result<()> {
+    let source = opts.source.as_str();
+
+    let fetched = if source == "-" {
+        let mut input = String::new();
+        stdin().read_to_string(&mut input)?;
+        decode_versioned(&input)?
+    } else if source.starts_with("http://") || source.starts_with("https://") {
+        task::block_on(fetch_versioned(source))?
+    } else {
+        let input = read_to_string(source).map_err(|e| Error::file(source, e))?;
+        decode_versioned(&input)?
+    };
+
+    let pub_info = match fetched.document {
+        Document::Identity(pub_info) => pub_info,
+        Document::Revocation(revocation) => {
+            eprintln!("\nThis is a revocation, signed by the key it revokes:");
+            eprintln!("  {}", revocation.pubkey);
+            eprintln!("\nThe key was revoked on {}.", revocation.revoked_at);
+            if let Some(ref reason) = revocation.reason {
+                eprintln!("Reason: {}", reason);
+            }
+            return Err(Error::Trust("Identity revoked!".into()));
+        }
+    };
+
+    if let Some(ref expected) = opts.pubkey {
+        if *expected != pub_info.pubkey {
+            if follow_successions(&pub_info.successions, expected, &pub_info.pubkey).is_none() {
+                eprintln!("\nThe file is signed by a different public key than expected:");
+                eprintln!("  expected: {}", expected);
+                eprintln!("  found:    {}", pub_info.pubkey);
+                return Err(Error::Trust("Public key mismatch!".into()));
+            }
+
+            eprintln!("\nThe expected key has rotated to a new public key:");
+            eprintln!("  {}", pub_info.pubkey);
+            eprintln!("The new key was signed by the expected key, so it is accepted.");
+        }
+    }
+
+    if let Some(expires) = pub_info.expires.filter(|_| pub_info.is_expired()) {
+        eprintln!(
+            "\nThe file expired on {}, and should be re-published.",
+            expires
+        );
+        return Err(Error::Trust("File expired!".into()));
+    }
+
+    if format != Format::Text {
+        return print_structured(
+            format,
+            &VerifiedFile {
+                format_version: fetched.version,
+                info: pub_info,
+            },
+        );
+    }
+
+    println!("Valid frauth file for '{}'", pub_info.name);
+    println!("\nPublic key:   {}", pub_info.pubkey);
+    println!(
+        "Status:       {}",
+        pub_info.status.as_deref().unwrap_or("<no status is set>")
+    );
+    match pub_info.last_updated {
+        Some(last_updated) => println!("Last updated: {}", last_updated),
+        None => println!("Last updated: <unknown>"),
+    }
+    match pub_info.expires {
+        Some(expires) => println!("Expires:      {}", expires),
+        None => println!("Expires:      <never>"),
+    }
+    println!("Format:       version {}", fetched.version);
+
+    println!("\nIdentities:");
+    for (name, id) in pub_info.identities.iter() {
+        println!("  - {}: {}", name, id);
+    }
+
+    println!("\nFriends:");
+    for friend in pub_info.friends.iter() {
+        match friend.trust {
+            Some(trust) => println!("  - {} (trust: {})", friend.uri, trust),
+            None => println!("  - {}", friend.uri),
+        }
+        println!("      {}", friend.pubkey);
+    }
+
+    Ok(())
+}
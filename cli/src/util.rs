@@ -1,20 +1,32 @@
 use std::{
-    fs::{read_to_string, File, OpenOptions},
-    io::Write,
-    path::Path,
+    env,
+    ffi::OsString,
+    fs::{copy, read_to_string, remove_file, rename, File, OpenOptions},
+    io::{stdin, IsTerminal, Write},
+    path::{Path, PathBuf},
 };
 
-use toml::{from_str, to_string};
+use async_std::{sync::channel, task};
+use dialoguer::{Confirmation, Input, PasswordInput};
+use ed25519_dalek::Keypair;
+use frauth_core::{decode_document, document_version, Document};
+use serde::{de::DeserializeOwned, Serialize};
+use surf::get;
+use toml::{from_str, to_string, Value};
 
 use crate::{
-    consts::USER_INFO_HEADER,
-    schema::{Friends, UserInfo},
-    {Error, Result, PATHS},
+    migrate::{backup, backup_path, migrate, DataFile, DATA_VERSION},
+    schema::{EncryptedKey, FriendInfo, Friends, Peers, PublishUserInfo, UserInfo},
+    error::NetworkError,
+    {Error, Result},
 };
 
 #[cfg(unix)]
 use std::os::unix::fs::OpenOptionsExt;
 
+/// The maximum number of requests to have in flight at once
+const MAX_IN_FLIGHT: usize = 16;
+
 pub fn create_private_file(path: &Path) -> Result<File> {
     let mut opt = OpenOptions::new();
     opt.write(true);
@@ -28,47 +40,353 @@ pub fn create_private_file(path: &Path) -> Result<File> {
     #[cfg(not(unix))]
     eprintln!(
         "Warning! You should set the permissions for {} to only be readable by this user!",
-        PATHS.user_info.display()
+        path.display()
     );
 
-    opt.open(path).map_err(|e| {
-        Error::from(format!("Failed to open file: {}\nReason: {}", path.display(), e).as_str())
-    })
+    opt.open(path).map_err(|e| Error::file(path, e))
 }
 
-fn open_existing_file(path: &Path) -> Result<File> {
-    let mut opt = OpenOptions::new();
-    opt.write(true);
+/// Replace the contents of a private file, without ever leaving it half written
+///
+/// The new contents are written and synced to a temporary file, which is then
+/// renamed over `path`. The previous contents, if any, are kept at `<path>.bak`.
+pub fn write_private_file(path: &Path, contents: &[u8]) -> Result<()> {
+    replace_file(path, contents, true)
+}
 
-    opt.open(path).map_err(|e| {
-        Error::from(format!("Failed to open file: {}\nReason: {}", path.display(), e).as_str())
-    })
+fn replace_file(path: &Path, contents: &[u8], keep_backup: bool) -> Result<()> {
+    let tmp_path = with_suffix(path, ".tmp");
+
+    let mut tmp_file = create_private_file(&tmp_path)?;
+    tmp_file
+        .write_all(contents)
+        .and_then(|_| tmp_file.sync_all())
+        .map_err(|e| Error::file(&tmp_path, e))?;
+    drop(tmp_file);
+
+    if keep_backup && path.exists() {
+        // `copy` also copies the permissions of the original
+        let bak_path = with_suffix(path, ".bak");
+        copy(path, &bak_path)
+            .and_then(|_| File::open(&bak_path)?.sync_all())
+            .map_err(|e| Error::file(&bak_path, e))?;
+    }
+
+    rename(&tmp_path, path).map_err(|e| Error::file(path, e))?;
+
+    // Make sure the rename itself has made it to disk
+    #[cfg(unix)]
+    {
+        if let Some(dir) = path.parent() {
+            File::open(dir)
+                .and_then(|dir| dir.sync_all())
+                .map_err(|e| Error::file(dir, e))?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Overwrite a file with zeros before removing it, if it exists
+///
+/// This is a best effort, as some filesystems and disks may still keep the
+/// old contents elsewhere.
+fn wipe_file(path: &Path) -> Result<()> {
+    if !path.exists() {
+        return Ok(());
+    }
+
+    let len = path.metadata().map_err(|e| Error::file(path, e))?.len();
+    let mut file = OpenOptions::new()
+        .write(true)
+        .open(path)
+        .map_err(|e| Error::file(path, e))?;
+    file.write_all(&vec![0u8; len as usize])
+        .and_then(|_| file.sync_all())
+        .map_err(|e| Error::file(path, e))?;
+    drop(file);
+
+    remove_file(path).map_err(|e| Error::file(path, e))
+}
+
+pub(crate) fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = OsString::from(path.as_os_str());
+    name.push(suffix);
+    name.into()
+}
+
+/// Read and parse one of our own data files
+///
+/// Files written by older versions of frauth are upgraded in place first.
+fn load_data_file<T: DeserializeOwned>(file: DataFile) -> Result<T> {
+    let path = file.path();
+    let parse_error = |e| Error::Data(format!("Failed to parse {}: {}", path.display(), e));
+
+    let mut contents = read_to_string(path).map_err(|e| Error::file(path, e))?;
+
+    let mut value: Value = from_str(&contents).map_err(parse_error)?;
+    if let Some(version) = migrate(file, &mut value)? {
+        backup(file, version)?;
+        contents = file.header().to_string() + &to_string(&value)?;
+        write_private_file(path, contents.as_bytes())?;
+    }
+
+    from_str(&contents).map_err(parse_error)
+}
+
+/// Write one of our own data files, marked with the current [`DATA_VERSION`]
+fn save_data_file<T: Serialize>(file: DataFile, data: &T) -> Result<()> {
+    write_private_file(file.path(), render_data_file(file, data)?.as_bytes())
+}
+
+fn render_data_file<T: Serialize>(file: DataFile, data: &T) -> Result<String> {
+    // The version has to come before any tables, so it goes at the very top
+    Ok(format!(
+        "{}version = {}\n\n{}",
+        file.header(),
+        DATA_VERSION,
+        to_string(data)?
+    ))
 }
 
 pub fn load_user_info() -> Result<UserInfo> {
-    let contents = read_to_string(&PATHS.user_info)?;
+    let user_info: UserInfo = load_data_file(DataFile::User)?;
+
+    if user_info.keypair.is_some() == user_info.encrypted_key.is_some() {
+        return Err(Error::Data(
+            "User info must contain exactly one keypair!".into(),
+        ));
+    }
+
+    Ok(user_info)
+}
+
+/// Get the user's keypair, prompting for their passphrase if one is set
+pub fn unlock_keypair(user_info: &UserInfo) -> Result<Keypair> {
+    match (&user_info.keypair, &user_info.encrypted_key) {
+        (Some(keypair), _) => Keypair::from_bytes(&keypair.to_bytes())
+            .map_err(|_| Error::Data("Stored keypair is invalid!".into())),
+        (None, Some(encrypted)) => {
+            let passphrase = prompt_passphrase()?;
+            encrypted
+                .open(&passphrase)
+                .map_err(|_| Error::Passphrase)
+        }
+        (None, None) => Err(Error::Data("No keypair found!".into())),
+    }
+}
+
+/// Store the user's keypair, encrypting it if a passphrase is given
+pub fn store_keypair(
+    user_info: &mut UserInfo,
+    keypair: Keypair,
+    passphrase: Option<&str>,
+) -> Result<()> {
+    match passphrase {
+        Some(passphrase) => {
+            user_info.encrypted_key = Some(EncryptedKey::seal(&keypair, passphrase)?);
+            user_info.keypair = None;
+        }
+        None => {
+            user_info.keypair = Some(keypair);
+            user_info.encrypted_key = None;
+        }
+    }
+    Ok(())
+}
+
+/// Read from this environment variable instead of prompting for the current passphrase
+pub const PASSPHRASE_VAR: &str = "FRAUTH_PASSPHRASE";
+
+/// Read from this environment variable instead of prompting for a new passphrase
+pub const NEW_PASSPHRASE_VAR: &str = "FRAUTH_NEW_PASSPHRASE";
+
+/// Fail instead of prompting when stdin is not a terminal, as nobody is there to answer
+///
+/// `alternative` names the flag or variable that can be used instead of the prompt.
+pub fn ensure_interactive(alternative: &str) -> Result<()> {
+    if stdin().is_terminal() {
+        Ok(())
+    } else {
+        Err(Error::Command(format!(
+            "stdin is not a terminal, so frauth can't prompt you. Use {} instead.",
+            alternative
+        )))
+    }
+}
+
+/// Ask a yes or no question
+pub fn confirm(text: &str, default: bool, alternative: &str) -> Result<bool> {
+    ensure_interactive(alternative)?;
+    Ok(Confirmation::new()
+        .default(default)
+        .with_text(text)
+        .interact()?)
+}
 
-    from_str(&contents)
-        .map_err(|e| {
-            println!("{:?}", e);
-            e
-        })
-        .map_err(|_| "Failed to parse user info!".into())
+/// Ask for a line of text
+pub fn prompt_input(prompt: &str, alternative: &str) -> Result<String> {
+    ensure_interactive(alternative)?;
+    Ok(Input::<String>::new().with_prompt(prompt).interact()?)
+}
+
+pub fn prompt_passphrase() -> Result<String> {
+    if let Ok(passphrase) = env::var(PASSPHRASE_VAR) {
+        return Ok(passphrase);
+    }
+
+    ensure_interactive(&format!("the {} environment variable", PASSPHRASE_VAR))?;
+    Ok(PasswordInput::new().with_prompt("Passphrase").interact()?)
+}
+
+pub fn prompt_new_passphrase() -> Result<String> {
+    if let Ok(passphrase) = env::var(NEW_PASSPHRASE_VAR) {
+        return Ok(passphrase);
+    }
+
+    ensure_interactive(&format!("the {} environment variable", NEW_PASSPHRASE_VAR))?;
+    Ok(PasswordInput::new()
+        .with_prompt("New passphrase")
+        .with_confirmation("Confirm passphrase", "Passphrases don't match!")
+        .interact()?)
 }
 
 pub fn write_user_info(user_info: &UserInfo) -> Result<()> {
-    let mut user_info_file = open_existing_file(&PATHS.user_info)?;
-    let contents = to_string(&user_info)?;
+    save_data_file(DataFile::User, user_info)
+}
+
+/// Write the user's info after their secret key, or its passphrase, has changed
+///
+/// No backup is kept, and any earlier backups are wiped, as they would still
+/// hold the old key, possibly without a passphrase.
+pub fn write_user_secrets(user_info: &UserInfo) -> Result<()> {
+    let file = DataFile::User;
+    let contents = render_data_file(file, user_info)?;
+    replace_file(file.path(), contents.as_bytes(), false)?;
 
-    user_info_file.set_len(0)?;
-    user_info_file.write_all(USER_INFO_HEADER.as_bytes())?;
-    user_info_file.write_all(contents.as_bytes())?;
+    wipe_file(&with_suffix(file.path(), ".bak"))?;
+    for version in 0..DATA_VERSION {
+        wipe_file(&backup_path(file, version))?;
+    }
 
     Ok(())
 }
 
 pub fn load_friends() -> Result<Friends> {
-    let contents = read_to_string(&PATHS.friend_info)?;
-    let data = from_str(&contents)?;
-    Ok(data)
+    load_data_file(DataFile::Friends)
+}
+
+pub fn save_friends(friends: &Friends) -> Result<()> {
+    save_data_file(DataFile::Friends, friends)
+}
+
+pub fn load_peers() -> Result<Peers> {
+    load_data_file(DataFile::Peers)
+}
+
+pub fn save_peers(peers: &Peers) -> Result<()> {
+    save_data_file(DataFile::Peers, peers)
+}
+
+/// A document fetched from an identity URL, and the format version it was published in
+pub struct Fetched {
+    pub document: Document,
+    pub version: u32,
+}
+
+/// Fetch and decode the document published at an identity URL
+pub async fn fetch_document(url: &str) -> Result<Document> {
+    Ok(fetch_versioned(url).await?.document)
+}
+
+/// Fetch and decode the document published at an identity URL, keeping its format version
+pub async fn fetch_versioned(url: &str) -> Result<Fetched> {
+    let network = |kind| Error::Network {
+        url: url.to_string(),
+        kind,
+    };
+
+    // Keep the error out of scope across awaits, so this can be spawned as a task
+    let mut response = get(url)
+        .await
+        .map_err(|e| network(NetworkError::classify(&e)))?;
+
+    if !response.status().is_success() {
+        return Err(network(NetworkError::Status(response.status().as_u16())));
+    }
+
+    let body = response
+        .body_string()
+        .await
+        .map_err(|e| network(NetworkError::Body(e.to_string())))?;
+
+    decode_versioned(&body)
+}
+
+/// Decode a document, keeping its format version
+pub fn decode_versioned(input: &str) -> Result<Fetched> {
+    Ok(Fetched {
+        document: decode_document(input)?,
+        version: document_version(input)?,
+    })
+}
+
+/// Fetch and decode the documents published at each URL, a few at a time
+///
+/// Results are returned in the same order as `urls`.
+pub async fn fetch_documents(urls: Vec<String>) -> Vec<(String, Result<Fetched>)> {
+    let (txs, rxs) = channel::<()>(MAX_IN_FLIGHT);
+
+    // Fill the counting semaphore
+    for _ in 0..MAX_IN_FLIGHT {
+        txs.send(()).await;
+    }
+
+    let mut handles = Vec::with_capacity(urls.len());
+
+    for url in urls {
+        // take a counting semaphore
+        let _ = rxs.recv().await;
+
+        let txs = txs.clone();
+
+        handles.push(task::spawn(async move {
+            let document = fetch_versioned(&url).await;
+
+            // Replace the counting semaphore
+            txs.send(()).await;
+
+            (url, document)
+        }));
+    }
+
+    let mut results = Vec::with_capacity(handles.len());
+    for handle in handles {
+        results.push(handle.await);
+    }
+    results
+}
+
+/// Print a warning if this friend has revoked their current key
+pub fn warn_if_revoked(url: &str, friend: &FriendInfo) {
+    if let Some(ref revocation) = friend.revoked {
+        eprintln!(
+            "\nWARNING! `{}` revoked their public key on {}.",
+            url, revocation.revoked_at
+        );
+        if let Some(ref reason) = revocation.reason {
+            eprintln!("Reason: {}", reason);
+        }
+        eprintln!("Anything signed or encrypted with this key should not be trusted!");
+        eprintln!();
+    }
+}
+
+/// Print a warning if this file has passed its expiry date
+pub fn warn_if_expired(url: &str, info: &PublishUserInfo) {
+    if let (true, Some(expires)) = (info.is_expired(), info.expires) {
+        eprintln!("\nWARNING! The file at `{}` expired on {}.", url, expires);
+        eprintln!("Its information may be stale, so ask them to re-publish.");
+        eprintln!();
+    }
 }
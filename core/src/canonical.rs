@@ -0,0 +1,105 @@
+use std::fmt::Write;
+
+use toml::Value;
+
+/// Encode a TOML value in the canonical form that signatures are made over
+///
+/// The encoding is compact JSON. Table keys are sorted by their UTF-8 bytes,
+/// there is no whitespace between tokens, and strings only escape `"`, `\`,
+/// and control characters (as `\u00XX`). Datetimes are encoded as strings,
+/// and floats in the shortest form that reads back as the same number.
+///
+/// Two bodies with the same contents always have the same encoding, no matter
+/// how their TOML was formatted.
+pub fn canonical(value: &Value) -> String {
+    let mut out = String::new();
+    write_value(&mut out, value);
+    out
+}
+
+fn write_value(out: &mut String, value: &Value) {
+    match value {
+        Value::String(s) => write_str(out, s),
+        Value::Integer(i) => {
+            let _ = write!(out, "{}", i);
+        }
+        Value::Float(f) => {
+            let _ = write!(out, "{:?}", f);
+        }
+        Value::Boolean(b) => {
+            let _ = write!(out, "{}", b);
+        }
+        Value::Datetime(dt) => write_str(out, &dt.to_string()),
+        Value::Array(items) => {
+            out.push('[');
+            for (i, item) in items.iter().enumerate() {
+                if i != 0 {
+                    out.push(',');
+                }
+                write_value(out, item);
+            }
+            out.push(']');
+        }
+        Value::Table(table) => {
+            let mut entries = table.iter().collect::<Vec<_>>();
+            entries.sort_by(|a, b| a.0.as_bytes().cmp(b.0.as_bytes()));
+
+            out.push('{');
+            for (i, (key, item)) in entries.into_iter().enumerate() {
+                if i != 0 {
+                    out.push(',');
+                }
+                write_str(out, key);
+                out.push(':');
+                write_value(out, item);
+            }
+            out.push('}');
+        }
+    }
+}
+
+fn write_str(out: &mut String, s: &str) {
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            c if (c as u32) < 0x20 || c == '\u{7f}' => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sorts_keys_and_drops_formatting() {
+        let a: Value = toml::from_str(
+            "b = 1\na = [true, false]\nf = 1.5\n[c]\nz = 'x'\ny = 1979-05-27T07:32:00Z\n",
+        )
+        .unwrap();
+        let b: Value = toml::from_str(
+            "f = 1.50\r\na=[ true,\n  false ]\r\nb   =   1\r\n\r\n[c]\r\ny = 1979-05-27T07:32:00Z\r\nz = \"x\"\r\n",
+        )
+        .unwrap();
+
+        let expected =
+            r#"{"a":[true,false],"b":1,"c":{"y":"1979-05-27T07:32:00Z","z":"x"},"f":1.5}"#;
+        assert_eq!(canonical(&a), expected);
+        assert_eq!(canonical(&b), expected);
+    }
+
+    #[test]
+    fn escapes_strings() {
+        let value = Value::String("\"quoted\" \\ tab\tnew\nline ünï\u{7f}".into());
+        assert_eq!(
+            canonical(&value),
+            "\"\\\"quoted\\\" \\\\ tab\\u0009new\\u000aline ünï\\u007f\""
+        );
+    }
+}
@@ -0,0 +1,506 @@
+//! Encryption of files to one or more friends
+//!
+//! frauth only has ed25519 keys, so these are converted to their X25519
+//! equivalents for key exchange. Each file is encrypted with a random
+//! content key, which is then wrapped once for each recipient using a key
+//! derived from two exchanges:
+//!
+//! * An ephemeral key with the recipient's key, so that the sender's key
+//!   alone can not be used to recover the content key later
+//! * The sender's key with the recipient's key, so that only the sender
+//!   could have wrapped the content key for that recipient
+//!
+//! The whole file is then signed by the sender, so that one recipient can
+//! not modify the contents and pass it off to another recipient as
+//! coming from the sender.
+
+use std::convert::TryInto;
+
+use base64::{decode as b64_decode, encode as b64_encode};
+use chacha20poly1305::{
+    aead::{Aead, NewAead},
+    ChaCha20Poly1305, Key, Nonce,
+};
+use curve25519_dalek::{
+    constants::X25519_BASEPOINT, edwards::CompressedEdwardsY, montgomery::MontgomeryPoint,
+    scalar::Scalar,
+};
+use ed25519_dalek::{Keypair, PublicKey, Signature};
+use rand::{rngs::OsRng, RngCore};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha512};
+
+use crate::{
+    error::{CryptError, DecodeError, EncodeError},
+    file::{
+        assert_or, expect_line, parse_public_key, parse_signature, parse_toml, HEADER_END_OF_FILE,
+        HEADER_SIGNATURE, HEADER_TOP,
+    },
+};
+
+pub const HEADER_ENCRYPTED: &str = "FRAUTH-ENCRYPTED\n";
+
+/// Context string used to separate derived keys from any other use of the same keys
+const KDF_CONTEXT: &[u8] = b"frauth-encrypt-v1";
+
+/// Each key is only ever used to encrypt a single message, so a fixed nonce is fine
+const NONCE: [u8; 12] = [0u8; 12];
+
+/// Line length used when wrapping the base64 encoded ciphertext
+const LINE_LENGTH: usize = 76;
+
+/// A file, encrypted to one or more recipients
+#[derive(Debug)]
+pub struct EncryptedFile {
+    /// The base64 encoded public key of the sender
+    pub sender: String,
+    ephemeral: [u8; 32],
+    recipients: Vec<WrappedKey>,
+    ciphertext: Vec<u8>,
+    signature: Signature,
+}
+
+/// The content key, wrapped for one recipient
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct WrappedKey {
+    /// The base64 encoded public key of the recipient
+    pubkey: String,
+    /// The base64 encoded, encrypted content key
+    key: String,
+}
+
+/// The part of the file that is rendered as TOML
+#[derive(Debug, Serialize, Deserialize)]
+struct Header {
+    sender: String,
+    ephemeral: String,
+    recipients: Vec<WrappedKey>,
+}
+
+impl EncryptedFile {
+    /// Encrypt `data` from `sender` to each of the `recipients`
+    ///
+    /// `recipients` are base64 encoded public keys, as they appear in
+    /// published files.
+    pub fn encrypt(data: &[u8], sender: &Keypair, recipients: &[&str]) -> Result<Self, CryptError> {
+        assert_or(!recipients.is_empty(), CryptError::NoRecipients)?;
+
+        let sender_pubkey = b64_encode(sender.public.as_bytes());
+        let sender_secret = secret_to_x25519(sender);
+
+        let mut ephemeral_bytes = [0u8; 32];
+        OsRng.fill_bytes(&mut ephemeral_bytes);
+        let ephemeral_secret = clamp(ephemeral_bytes);
+        let ephemeral = (X25519_BASEPOINT * ephemeral_secret).to_bytes();
+
+        let mut content_key = [0u8; 32];
+        OsRng.fill_bytes(&mut content_key);
+
+        let mut wrapped = Vec::with_capacity(recipients.len());
+        for recipient in recipients {
+            let recipient_x = pubkey_to_x25519(recipient).ok_or(CryptError::PublicKey)?;
+
+            let ephemeral_shared = shared_secret(&ephemeral_secret, &recipient_x)?;
+            let static_shared = shared_secret(&sender_secret, &recipient_x)?;
+
+            let wrap_key = derive_key(
+                &ephemeral,
+                &sender_pubkey,
+                recipient,
+                &ephemeral_shared,
+                &static_shared,
+            );
+            let key = seal(&wrap_key, &content_key)?;
+
+            wrapped.push(WrappedKey {
+                pubkey: recipient.to_string(),
+                key: b64_encode(&key),
+            });
+        }
+
+        let ciphertext = seal(&content_key, data)?;
+
+        let signature = sender.sign(&transcript(
+            &sender_pubkey,
+            &ephemeral,
+            &wrapped,
+            &ciphertext,
+        ));
+
+        Ok(EncryptedFile {
+            sender: sender_pubkey,
+            ephemeral,
+            recipients: wrapped,
+            ciphertext,
+            signature,
+        })
+    }
+
+    /// Render the encrypted file to a plain text string
+    pub fn render(&self) -> Result<String, EncodeError> {
+        let header = Header {
+            sender: self.sender.clone(),
+            ephemeral: b64_encode(&self.ephemeral),
+            recipients: self.recipients.clone(),
+        };
+
+        let ciphertext = b64_encode(&self.ciphertext);
+
+        let mut contents = String::new();
+        contents += HEADER_ENCRYPTED;
+        contents += &toml::to_string(&header).map_err(EncodeError::Toml)?;
+        contents += HEADER_TOP;
+        for chunk in ciphertext.as_bytes().chunks(LINE_LENGTH) {
+            // base64 is always ASCII, so chunking can't split a character
+            contents += &String::from_utf8_lossy(chunk);
+            contents += "\n";
+        }
+        contents += HEADER_SIGNATURE;
+        contents += &b64_encode(&self.signature.to_bytes()[..]);
+        contents += "\n";
+        contents += HEADER_END_OF_FILE;
+
+        Ok(contents)
+    }
+
+    /// Parse a rendered encrypted file
+    ///
+    /// This does not check the signature or decrypt anything. Use
+    /// [`EncryptedFile::decrypt`] for that.
+    pub fn parse(input: &str) -> Result<Self, DecodeError> {
+        let lines = input.trim().lines().collect::<Vec<_>>();
+        let len = lines.len();
+
+        assert_or(
+            len >= 6,
+            DecodeError::layout("at least 6 lines", format!("{}", len)),
+        )?;
+        expect_line(&lines, 0, HEADER_ENCRYPTED)?;
+        expect_line(&lines, len - 3, HEADER_SIGNATURE)?;
+        expect_line(&lines, len - 1, HEADER_END_OF_FILE)?;
+
+        let contents_idx = lines
+            .iter()
+            .position(|l| *l == HEADER_TOP.trim())
+            .ok_or_else(|| {
+                DecodeError::layout(format!("a `{}` line", HEADER_TOP.trim()), "none")
+            })?;
+        assert_or(
+            contents_idx > 1,
+            DecodeError::layout_at(1, "a header listing the sender and recipients"),
+        )?;
+        assert_or(
+            contents_idx < len - 3,
+            DecodeError::layout_at(contents_idx + 1, "encrypted contents"),
+        )?;
+
+        let mut header_toml = lines[1..contents_idx].join("\n");
+        header_toml += "\n";
+        let header: Header = parse_toml(&header_toml, 1)?;
+
+        parse_public_key(&header.sender, None)?;
+
+        let bad_ephemeral = || {
+            DecodeError::layout(
+                "a base64 encoded 32 byte `ephemeral` key",
+                format!("`{}`", header.ephemeral),
+            )
+        };
+        let ephemeral: [u8; 32] = b64_decode(&header.ephemeral)
+            .map_err(|_| bad_ephemeral())?
+            .as_slice()
+            .try_into()
+            .map_err(|_| bad_ephemeral())?;
+
+        let ciphertext = b64_decode(&lines[contents_idx + 1..len - 3].concat())
+            .map_err(|_| DecodeError::layout_at(contents_idx + 1, "base64 encoded contents"))?;
+
+        let signature = parse_signature(lines[len - 2], Some(len - 1))?;
+
+        Ok(EncryptedFile {
+            sender: header.sender,
+            ephemeral,
+            recipients: header.recipients,
+            ciphertext,
+            signature,
+        })
+    }
+
+    /// The base64 encoded public keys of all recipients
+    pub fn recipients(&self) -> impl Iterator<Item = &str> {
+        self.recipients.iter().map(|r| r.pubkey.as_str())
+    }
+
+    /// Check the sender's signature, and decrypt the file with the recipient's keypair
+    ///
+    /// On success, the file was sent by the public key in `sender`. It is up
+    /// to the caller to decide whether that key is trusted.
+    pub fn decrypt(&self, recipient: &Keypair) -> Result<Vec<u8>, CryptError> {
+        let sender_bytes = b64_decode(&self.sender).map_err(|_| CryptError::PublicKey)?;
+        let sender_key = PublicKey::from_bytes(&sender_bytes).map_err(|_| CryptError::PublicKey)?;
+
+        sender_key
+            .verify(
+                &transcript(
+                    &self.sender,
+                    &self.ephemeral,
+                    &self.recipients,
+                    &self.ciphertext,
+                ),
+                &self.signature,
+            )
+            .map_err(|_| CryptError::Verification)?;
+
+        let recipient_pubkey = b64_encode(recipient.public.as_bytes());
+        let wrapped = self
+            .recipients
+            .iter()
+            .find(|r| r.pubkey == recipient_pubkey)
+            .ok_or(CryptError::NotARecipient)?;
+        let wrapped_key = b64_decode(&wrapped.key).map_err(|_| CryptError::Decryption)?;
+
+        let recipient_secret = secret_to_x25519(recipient);
+        let ephemeral = MontgomeryPoint(self.ephemeral);
+        let sender_x = pubkey_to_x25519(&self.sender).ok_or(CryptError::PublicKey)?;
+
+        let ephemeral_shared = shared_secret(&recipient_secret, &ephemeral)?;
+        let static_shared = shared_secret(&recipient_secret, &sender_x)?;
+
+        let wrap_key = derive_key(
+            &self.ephemeral,
+            &self.sender,
+            &recipient_pubkey,
+            &ephemeral_shared,
+            &static_shared,
+        );
+
+        let content_key: [u8; 32] = open(&wrap_key, &wrapped_key)?
+            .as_slice()
+            .try_into()
+            .map_err(|_| CryptError::Decryption)?;
+
+        open(&content_key, &self.ciphertext)
+    }
+}
+
+/// Convert a base64 encoded ed25519 public key to an X25519 public key
+fn pubkey_to_x25519(pubkey: &str) -> Option<MontgomeryPoint> {
+    let bytes: [u8; 32] = b64_decode(pubkey).ok()?.as_slice().try_into().ok()?;
+    let point = CompressedEdwardsY(bytes).decompress()?;
+    Some(point.to_montgomery())
+}
+
+/// Convert an ed25519 secret key to an X25519 secret scalar
+///
+/// This is the same expansion ed25519 performs on the secret key before signing
+fn secret_to_x25519(keypair: &Keypair) -> Scalar {
+    let hash = Sha512::digest(keypair.secret.as_bytes());
+    let mut bytes = [0u8; 32];
+    bytes.copy_from_slice(&hash[..32]);
+    clamp(bytes)
+}
+
+fn clamp(mut bytes: [u8; 32]) -> Scalar {
+    bytes[0] &= 248;
+    bytes[31] &= 127;
+    bytes[31] |= 64;
+    Scalar::from_bits(bytes)
+}
+
+fn shared_secret(secret: &Scalar, public: &MontgomeryPoint) -> Result<[u8; 32], CryptError> {
+    let shared = (public * secret).to_bytes();
+
+    // Reject low order points, which would result in a predictable shared secret
+    assert_or(shared != [0u8; 32], CryptError::PublicKey)?;
+
+    Ok(shared)
+}
+
+fn derive_key(
+    ephemeral: &[u8; 32],
+    sender: &str,
+    recipient: &str,
+    ephemeral_shared: &[u8; 32],
+    static_shared: &[u8; 32],
+) -> [u8; 32] {
+    let mut hasher = Sha512::new();
+    hasher.input(KDF_CONTEXT);
+    hasher.input(ephemeral);
+    hasher.input(sender.as_bytes());
+    hasher.input(recipient.as_bytes());
+    hasher.input(ephemeral_shared);
+    hasher.input(static_shared);
+    let hash = hasher.result();
+
+    let mut key = [0u8; 32];
+    key.copy_from_slice(&hash[..32]);
+    key
+}
+
+/// The bytes covered by the sender's signature
+fn transcript(
+    sender: &str,
+    ephemeral: &[u8; 32],
+    recipients: &[WrappedKey],
+    ciphertext: &[u8],
+) -> Vec<u8> {
+    // Every field is length prefixed, so fields can't be shifted into each other
+    fn push(out: &mut Vec<u8>, field: &[u8]) {
+        out.extend_from_slice(&(field.len() as u64).to_le_bytes());
+        out.extend_from_slice(field);
+    }
+
+    let mut out = Vec::new();
+    push(&mut out, HEADER_ENCRYPTED.as_bytes());
+    push(&mut out, sender.as_bytes());
+    push(&mut out, ephemeral);
+    push(&mut out, &(recipients.len() as u64).to_le_bytes());
+    for recipient in recipients {
+        push(&mut out, recipient.pubkey.as_bytes());
+        push(&mut out, recipient.key.as_bytes());
+    }
+    push(&mut out, ciphertext);
+    out
+}
+
+pub(crate) fn seal(key: &[u8; 32], data: &[u8]) -> Result<Vec<u8>, CryptError> {
+    ChaCha20Poly1305::new(&Key::from(*key))
+        .encrypt(&Nonce::from(NONCE), data)
+        .map_err(|_| CryptError::Encryption)
+}
+
+pub(crate) fn open(key: &[u8; 32], data: &[u8]) -> Result<Vec<u8>, CryptError> {
+    ChaCha20Poly1305::new(&Key::from(*key))
+        .decrypt(&Nonce::from(NONCE), data)
+        .map_err(|_| CryptError::Decryption)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::keypair;
+
+    #[test]
+    fn round_trip_to_several_recipients() {
+        let (sender, sender_pubkey) = keypair();
+        let (alice, alice_pubkey) = keypair();
+        let (bob, bob_pubkey) = keypair();
+
+        let file = EncryptedFile::encrypt(b"secret", &sender, &[&alice_pubkey, &bob_pubkey])
+            .unwrap()
+            .render()
+            .unwrap();
+        let parsed = EncryptedFile::parse(&file).unwrap();
+
+        assert_eq!(parsed.sender, sender_pubkey);
+        assert_eq!(
+            parsed.recipients().collect::<Vec<_>>(),
+            vec![alice_pubkey.as_str(), bob_pubkey.as_str()]
+        );
+        assert_eq!(parsed.decrypt(&alice).unwrap(), b"secret");
+        assert_eq!(parsed.decrypt(&bob).unwrap(), b"secret");
+    }
+
+    #[test]
+    fn needs_at_least_one_recipient() {
+        let (sender, _) = keypair();
+
+        assert!(matches!(
+            EncryptedFile::encrypt(b"secret", &sender, &[]),
+            Err(CryptError::NoRecipients)
+        ));
+    }
+
+    #[test]
+    fn others_cannot_decrypt() {
+        let (sender, _) = keypair();
+        let (_, alice_pubkey) = keypair();
+        let (eve, _) = keypair();
+
+        let file = EncryptedFile::encrypt(b"secret", &sender, &[&alice_pubkey]).unwrap();
+
+        assert!(matches!(file.decrypt(&eve), Err(CryptError::NotARecipient)));
+        assert!(matches!(
+            file.decrypt(&sender),
+            Err(CryptError::NotARecipient)
+        ));
+    }
+
+    #[test]
+    fn tampered_ciphertext_is_rejected() {
+        let (sender, _) = keypair();
+        let (alice, alice_pubkey) = keypair();
+
+        let mut file = EncryptedFile::encrypt(b"secret", &sender, &[&alice_pubkey]).unwrap();
+        file.ciphertext[0] ^= 1;
+
+        assert!(matches!(
+            file.decrypt(&alice),
+            Err(CryptError::Verification)
+        ));
+    }
+
+    #[test]
+    fn tampered_recipients_are_rejected() {
+        let (sender, _) = keypair();
+        let (alice, alice_pubkey) = keypair();
+        let (_, bob_pubkey) = keypair();
+
+        // A recipient can't quietly drop the others from the list...
+        let mut file =
+            EncryptedFile::encrypt(b"secret", &sender, &[&alice_pubkey, &bob_pubkey]).unwrap();
+        file.recipients.pop();
+        assert!(matches!(
+            file.decrypt(&alice),
+            Err(CryptError::Verification)
+        ));
+
+        // ...or swap in a wrapped key of their own
+        let mut file = EncryptedFile::encrypt(b"secret", &sender, &[&alice_pubkey]).unwrap();
+        let other = EncryptedFile::encrypt(b"other", &sender, &[&alice_pubkey]).unwrap();
+        file.recipients[0].key = other.recipients[0].key.clone();
+        assert!(matches!(
+            file.decrypt(&alice),
+            Err(CryptError::Verification)
+        ));
+    }
+
+    #[test]
+    fn wrong_sender_is_rejected() {
+        let (sender, _) = keypair();
+        let (alice, alice_pubkey) = keypair();
+        let (_, mallory_pubkey) = keypair();
+
+        let mut file = EncryptedFile::encrypt(b"secret", &sender, &[&alice_pubkey]).unwrap();
+        file.sender = mallory_pubkey;
+
+        assert!(matches!(
+            file.decrypt(&alice),
+            Err(CryptError::Verification)
+        ));
+    }
+
+    #[test]
+    fn tampered_rendering_is_rejected() {
+        let (sender, _) = keypair();
+        let (alice, alice_pubkey) = keypair();
+        let (_, mallory_pubkey) = keypair();
+
+        let file = EncryptedFile::encrypt(b"secret", &sender, &[&alice_pubkey])
+            .unwrap()
+            .render()
+            .unwrap();
+        let forged = file.replacen(
+            &format!("sender = \"{}\"", b64_encode(sender.public.as_bytes())),
+            &format!("sender = \"{}\"", mallory_pubkey),
+            1,
+        );
+        assert_ne!(file, forged);
+
+        let parsed = EncryptedFile::parse(&forged).unwrap();
+        assert!(matches!(
+            parsed.decrypt(&alice),
+            Err(CryptError::Verification)
+        ));
+    }
+}
@@ -0,0 +1,156 @@
+use base64::encode as b64_encode;
+use ed25519_dalek::{Keypair, Signature};
+use sha2::{Digest, Sha512};
+
+use crate::{
+    error::DecodeError,
+    file::{
+        assert_or, check_signature, expect_line, parse_public_key, parse_signature,
+        HEADER_END_OF_FILE, HEADER_SIGNATURE,
+    },
+};
+
+pub const HEADER_SIGNER: &str = "FRAUTH-SIGNER\n";
+
+/// The line of a `.sig` file that holds the signature, counting from 1
+const SIGNATURE_LINE: usize = 4;
+
+/// The first line of the bytes a detached signature is made over
+const DETACHED_CONTEXT: &str = "FRAUTH-DETACHED\n";
+
+/// A signature over some data, stored separately from the data itself
+#[derive(Debug)]
+pub struct DetachedSignature {
+    /// The base64 encoded public key that claims to have made this signature
+    pub signer: String,
+    pub signature: Signature,
+}
+
+impl DetachedSignature {
+    /// Sign `data` with the given keypair
+    pub fn sign(data: &[u8], keypair: &Keypair) -> Self {
+        DetachedSignature {
+            signer: b64_encode(keypair.public.as_bytes()),
+            signature: keypair.sign(&signed_bytes(data)),
+        }
+    }
+
+    /// Render the signature to a string, suitable for writing to a `.sig` file
+    pub fn render(&self) -> String {
+        let mut contents = String::new();
+        contents += HEADER_SIGNER;
+        contents += &self.signer;
+        contents += "\n";
+        contents += HEADER_SIGNATURE;
+        contents += &b64_encode(&self.signature.to_bytes()[..]);
+        contents += "\n";
+        contents += HEADER_END_OF_FILE;
+        contents
+    }
+
+    /// Parse a rendered signature
+    ///
+    /// This does not check the signature against any data. Use
+    /// [`DetachedSignature::verify`] for that.
+    pub fn parse(input: &str) -> Result<Self, DecodeError> {
+        let lines = input.trim().lines().collect::<Vec<_>>();
+
+        assert_or(
+            lines.len() == 5,
+            DecodeError::layout("exactly 5 lines", format!("{}", lines.len())),
+        )?;
+        expect_line(&lines, 0, HEADER_SIGNER)?;
+        expect_line(&lines, 2, HEADER_SIGNATURE)?;
+        expect_line(&lines, 4, HEADER_END_OF_FILE)?;
+
+        let signer = lines[1].trim().to_string();
+        parse_public_key(&signer, Some(2))?;
+
+        let signature = parse_signature(lines[3], Some(SIGNATURE_LINE))?;
+
+        Ok(DetachedSignature { signer, signature })
+    }
+
+    /// Check that this signature was made over `data` by `pubkey`
+    ///
+    /// `pubkey` is the base64 encoded public key, as it appears in published
+    /// files. It does not need to match the `signer` field.
+    pub fn verify(&self, data: &[u8], pubkey: &str) -> Result<(), DecodeError> {
+        check_signature(
+            pubkey,
+            &signed_bytes(data),
+            &self.signature,
+            Some(SIGNATURE_LINE),
+        )
+    }
+}
+
+/// The bytes a detached signature is made over
+///
+/// The data is arbitrary, so it is never signed directly. Otherwise, signing
+/// a file that happens to hold the signed bytes of a frauth file, succession,
+/// or revocation would forge one. Instead, the signature covers a line that no
+/// other document starts with, then the length and SHA-512 hash of the data.
+fn signed_bytes(data: &[u8]) -> Vec<u8> {
+    let mut signed = DETACHED_CONTEXT.as_bytes().to_vec();
+    signed.extend_from_slice(&(data.len() as u64).to_be_bytes());
+    signed.extend_from_slice(&Sha512::digest(data));
+    signed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        file::{decode, HEADER_TOP},
+        testing::keypair,
+    };
+
+    #[test]
+    fn round_trip() {
+        let (keypair, pubkey) = keypair();
+
+        let sig = DetachedSignature::sign(b"some file", &keypair);
+        let parsed = DetachedSignature::parse(&sig.render()).unwrap();
+
+        assert_eq!(parsed.signer, pubkey);
+        parsed.verify(b"some file", &pubkey).unwrap();
+        assert!(parsed.verify(b"another file", &pubkey).is_err());
+    }
+
+    #[test]
+    fn cannot_forge_a_frauth_file() {
+        let (keypair, pubkey) = keypair();
+
+        let body = format!(
+            "name = \"forged\"\npubkey = \"{}\"\nfriends = []\n\n[identities]\n",
+            pubkey
+        );
+        let sig = DetachedSignature::sign(body.as_bytes(), &keypair);
+
+        let forged = format!(
+            "{}{}{}{}\n{}",
+            HEADER_TOP,
+            body,
+            HEADER_SIGNATURE,
+            b64_encode(&sig.signature.to_bytes()[..]),
+            HEADER_END_OF_FILE
+        );
+
+        assert!(matches!(
+            decode(&forged),
+            Err(DecodeError::Verification { .. })
+        ));
+    }
+
+    #[test]
+    fn surrounding_blank_lines_are_ignored() {
+        let (keypair, pubkey) = keypair();
+
+        let sig = DetachedSignature::sign(b"some file", &keypair).render();
+        let padded = format!("\n{}\n\n", sig);
+
+        let parsed = DetachedSignature::parse(&padded).unwrap();
+        parsed.verify(b"some file", &pubkey).unwrap();
+    }
+}
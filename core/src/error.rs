@@ -0,0 +1,196 @@
+use std::fmt;
+
+/// Errors that can occur when rendering a frauth file
+#[derive(Debug)]
+pub enum EncodeError {
+    /// The contents could not be serialized
+    Toml(toml::ser::Error),
+
+    /// The public key in the contents does not belong to the signing keypair
+    PublicKeyMismatch,
+
+    /// A field that is required by the current format version is missing
+    Missing(&'static str),
+}
+
+/// Errors that can occur when decoding or verifying a frauth file
+#[derive(Debug)]
+pub enum DecodeError {
+    /// The headers, body, or signature are not where they should be
+    Layout(LayoutError),
+
+    /// The document uses a newer format version than this crate understands
+    Version(u32),
+
+    /// The body could not be deserialized. Line numbers count from the top of the document
+    Toml(toml::de::Error),
+
+    /// A field that is required by the format version of the document is missing
+    Missing(&'static str),
+
+    /// The signature is not a valid ed25519 signature. `line` is where the
+    /// signature is, counting from 1, if it is on a line of its own
+    Signature { line: Option<usize> },
+
+    /// The signature does not match the body. `line` is where the signature is
+    Verification { line: Option<usize> },
+
+    /// The public key is not a valid ed25519 public key. `line` is where the
+    /// key is, if it is on a line of its own rather than in the body
+    PublicKey { line: Option<usize> },
+
+    /// The public key in the body is not the one that was expected
+    PublicKeyMismatch,
+}
+
+/// Where and how the layout of a document differs from what was expected
+#[derive(Debug)]
+pub struct LayoutError {
+    /// The line the problem was found on, counting from 1. Unset if the
+    /// problem is with the document as a whole
+    pub line: Option<usize>,
+
+    /// What should have been there
+    pub expected: String,
+
+    /// What was there instead, if it is worth showing
+    pub found: Option<String>,
+}
+
+impl DecodeError {
+    /// A layout problem with the document as a whole
+    pub(crate) fn layout(expected: impl Into<String>, found: impl Into<String>) -> Self {
+        DecodeError::Layout(LayoutError {
+            line: None,
+            expected: expected.into(),
+            found: Some(found.into()),
+        })
+    }
+
+    /// A layout problem on the line at `index`, counting from 0
+    pub(crate) fn layout_at(index: usize, expected: impl Into<String>) -> Self {
+        DecodeError::Layout(LayoutError {
+            line: Some(index + 1),
+            expected: expected.into(),
+            found: None,
+        })
+    }
+}
+
+/// Errors that can occur when encrypting or decrypting a file
+#[derive(Debug)]
+pub enum CryptError {
+    /// No recipients were given
+    NoRecipients,
+
+    /// A public key is not a valid ed25519 public key, or is unsuitable for key exchange
+    PublicKey,
+
+    /// The file was not encrypted to the given keypair
+    NotARecipient,
+
+    /// The file could not be encrypted
+    Encryption,
+
+    /// The file could not be decrypted
+    Decryption,
+
+    /// The signature does not match the sender and contents
+    Verification,
+}
+
+impl fmt::Display for EncodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EncodeError::Toml(e) => write!(f, "Failed to serialize contents: {}", e),
+            EncodeError::PublicKeyMismatch => {
+                write!(f, "Public key does not match the signing keypair")
+            }
+            EncodeError::Missing(field) => write!(f, "Contents are missing `{}`", field),
+        }
+    }
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::Layout(e) => write!(f, "{}", e),
+            DecodeError::Version(v) => write!(
+                f,
+                "Format version {} is not supported, a newer version of frauth is needed",
+                v
+            ),
+            DecodeError::Toml(e) => write!(f, "Failed to deserialize contents: {}", e),
+            DecodeError::Missing(field) => write!(
+                f,
+                "Contents are missing `{}`, which is required by this format version",
+                field
+            ),
+            DecodeError::Signature { line: Some(line) } => {
+                write!(f, "Signature on line {} is invalid", line)
+            }
+            DecodeError::Signature { line: None } => write!(f, "Signature is invalid"),
+            DecodeError::Verification { line: Some(line) } => {
+                write!(f, "Signature on line {} does not match contents", line)
+            }
+            DecodeError::Verification { line: None } => {
+                write!(f, "Signature does not match contents")
+            }
+            DecodeError::PublicKey { line: Some(line) } => {
+                write!(f, "Public key on line {} is invalid", line)
+            }
+            DecodeError::PublicKey { line: None } => write!(f, "Public key is invalid"),
+            DecodeError::PublicKeyMismatch => write!(f, "Public key does not match"),
+        }
+    }
+}
+
+impl fmt::Display for LayoutError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.line {
+            Some(line) => write!(f, "File layout is invalid on line {}", line)?,
+            None => write!(f, "File layout is invalid")?,
+        }
+        write!(f, ": expected {}", self.expected)?;
+        if let Some(ref found) = self.found {
+            write!(f, ", found {}", found)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for CryptError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CryptError::NoRecipients => write!(f, "No recipients were given"),
+            CryptError::PublicKey => write!(f, "Public key is invalid"),
+            CryptError::NotARecipient => write!(f, "File was not encrypted to your key"),
+            CryptError::Encryption => write!(f, "Failed to encrypt"),
+            CryptError::Decryption => write!(f, "Failed to decrypt"),
+            CryptError::Verification => write!(f, "Signature does not match sender and contents"),
+        }
+    }
+}
+
+impl std::error::Error for EncodeError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            EncodeError::Toml(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            DecodeError::Layout(e) => Some(e),
+            DecodeError::Toml(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl std::error::Error for LayoutError {}
+
+impl std::error::Error for CryptError {}
@@ -0,0 +1,539 @@
+use base64::{decode as b64_decode, encode as b64_encode};
+use ed25519_dalek::{Keypair, PublicKey, Signature};
+use serde::{de::DeserializeOwned, Serialize};
+use toml::Value;
+
+use crate::{
+    canonical::canonical,
+    error::{DecodeError, EncodeError, LayoutError},
+    revocation::{Revocation, HEADER_REVOCATION},
+    schema::PublishUserInfo,
+};
+
+pub const HEADER_TOP: &str = "FRAUTH-CONTENTS\n";
+pub const HEADER_SIGNATURE: &str = "FRAUTH-SIGNATURE\n";
+pub const HEADER_END_OF_FILE: &str = "FRAUTH-ENDOFFILE\n";
+
+/// The version of the signed document format written by this crate
+///
+/// This covers both how documents are signed, and the schema of their
+/// contents. Bump it for any change that older versions can't read.
+///
+/// Version 1 documents, from frauth 0.2.x, have no version on their first
+/// line, and are signed over the TOML body exactly as written. Only frauth
+/// files existed then, so other kinds of document must always be versioned.
+/// From version 2:
+///
+/// * the first line ends with the version
+/// * the signature is made over the first line and the
+///   [canonical](crate::canonical::canonical) encoding of the body, so that
+///   reformatting the TOML or changing its line endings doesn't break it
+/// * `last_updated` is required in identity files
+pub const FORMAT_VERSION: u32 = 2;
+
+/// Render and sign a frauth file, suitable for publishing
+///
+/// The public key contained in `pub_info` must belong to `keypair`, and
+/// `last_updated` must be set.
+pub fn encode(pub_info: &PublishUserInfo, keypair: &Keypair) -> Result<String, EncodeError> {
+    if pub_info.pubkey != b64_encode(keypair.public.as_bytes()) {
+        return Err(EncodeError::PublicKeyMismatch);
+    }
+    if pub_info.last_updated.is_none() {
+        return Err(EncodeError::Missing("last_updated"));
+    }
+
+    sign_and_armor(HEADER_TOP, pub_info, keypair)
+}
+
+/// Decode a frauth file, checking that it is signed by the public key it contains
+///
+/// This only proves that the file is self-consistent. To check that the file
+/// belongs to a known identity, use [`verify`] instead.
+pub fn decode(input: &str) -> Result<PublishUserInfo, DecodeError> {
+    let armored = unarmor(input, HEADER_TOP)?;
+
+    // Check 6: Make sure toml de-tomls
+    let pub_info: PublishUserInfo = parse_toml(&armored.body, 1)?;
+
+    // Check 7: Make sure toml matches signature
+    check_signature(
+        &pub_info.pubkey,
+        &armored.signed,
+        &armored.signature,
+        Some(armored.signature_line),
+    )?;
+
+    // Check 8: Make sure fields that are only optional in older versions are set
+    assert_or(
+        armored.version < 2 || pub_info.last_updated.is_some(),
+        DecodeError::Missing("last_updated"),
+    )?;
+
+    Ok(pub_info)
+}
+
+/// The parts of an armored document
+pub(crate) struct Armored {
+    /// The format version from the first line
+    pub version: u32,
+
+    /// The TOML body, with `\n` line endings
+    pub body: String,
+
+    /// The bytes the signature should have been made over
+    pub signed: Vec<u8>,
+
+    pub signature: Signature,
+
+    /// Where the signature is, counting from 1
+    pub signature_line: usize,
+}
+
+/// Serialize, sign, and armor the contents of a document
+///
+/// `header` is the first line of the document, such as [`HEADER_TOP`].
+pub(crate) fn sign_and_armor<T: Serialize>(
+    header: &str,
+    contents: &T,
+    keypair: &Keypair,
+) -> Result<String, EncodeError> {
+    let toml_contents = toml::to_string(contents).map_err(EncodeError::Toml)?;
+    let value = Value::try_from(contents).map_err(EncodeError::Toml)?;
+    let sig = keypair.sign(&signed_bytes(header, FORMAT_VERSION, &value));
+
+    Ok(armor(header, &toml_contents, &sig))
+}
+
+/// Split an armored document into its body and signature
+///
+/// `header` is the first line of the document, such as [`HEADER_TOP`].
+/// Whitespace around the document and `\r\n` line endings are ignored.
+pub(crate) fn unarmor(input: &str, header: &str) -> Result<Armored, DecodeError> {
+    // TODO: This could probably be done in a way more efficient way
+    // that doesn't require splitting the content into lines and collecting
+    // them and recombining them. But that isn't a big deal for now
+
+    let lines = input.trim().lines().collect::<Vec<_>>();
+
+    // Check 0: There are at least some lines. We need at least:
+    // * A top header
+    // * At least one body line
+    // * A signature header
+    // * A signature
+    // * An End of File footer
+    assert_or(
+        lines.len() >= 5,
+        DecodeError::layout("at least 5 lines", format!("{}", lines.len())),
+    )?;
+
+    // Check 1: Make sure first line is sane, and we understand the version
+    let version = parse_header(lines[0], header)?;
+
+    // Check 2: Make sure last line is sane
+    expect_line(&lines, lines.len() - 1, HEADER_END_OF_FILE)?;
+
+    // Check 3: Make sure only one middle divider
+    let lines_body = &lines[1..lines.len()];
+
+    let dividers = lines_body
+        .iter()
+        .enumerate()
+        .filter_map(|(i, x)| {
+            if *x == HEADER_SIGNATURE.trim() {
+                Some(i)
+            } else {
+                None
+            }
+        })
+        .collect::<Vec<_>>();
+
+    assert_or(
+        dividers.len() == 1,
+        DecodeError::layout(
+            format!("exactly one `{}` line", HEADER_SIGNATURE.trim()),
+            format!("{}", dividers.len()),
+        ),
+    )?;
+
+    let pivot = dividers[0];
+
+    // `pivot` is counted from the second line
+    assert_or(
+        pivot != 0,
+        DecodeError::layout_at(1, "at least one line of contents"),
+    )?;
+    assert_or(
+        pivot != (lines_body.len() - 1),
+        DecodeError::layout_at(pivot + 1, "a signature"),
+    )?;
+
+    let (toml_body, sig_plus) = lines_body.split_at(pivot);
+    let (_hdr, sig_body_lines) = sig_plus.split_at(1);
+
+    assert_or(
+        sig_body_lines.len() == 2,
+        DecodeError::layout_at(
+            pivot + 3,
+            format!("`{}` after the signature", HEADER_END_OF_FILE.trim()),
+        ),
+    )?;
+
+    // Check 4: Make sure signature parses
+    let signature_line = pivot + 3;
+    let signature = parse_signature(sig_body_lines[0], Some(signature_line))?;
+
+    let mut body = toml_body.join("\n");
+    body += "\n";
+
+    // Check 5: Work out what was signed. From version 2 that is the canonical
+    // encoding of the body, so the body must at least be valid TOML
+    let signed = match version {
+        1 => body.clone().into_bytes(),
+        _ => signed_bytes(header, version, &parse_toml(&body, 1)?),
+    };
+
+    Ok(Armored {
+        version,
+        body,
+        signed,
+        signature,
+        signature_line,
+    })
+}
+
+/// The format version of a document, from its first line
+///
+/// This doesn't check the rest of the document. Callers that have already
+/// seen a newer version from the same identity can use this to refuse older
+/// documents, which are signed less strictly.
+pub fn document_version(input: &str) -> Result<u32, DecodeError> {
+    let first = input.trim().lines().next().unwrap_or("");
+
+    if first.starts_with(HEADER_REVOCATION.trim()) {
+        parse_header(first, HEADER_REVOCATION)
+    } else {
+        parse_header(first, HEADER_TOP)
+    }
+}
+
+/// Check the first line of a document, returning its format version
+fn parse_header(line: &str, header: &str) -> Result<u32, DecodeError> {
+    let header = header.trim();
+
+    // Only frauth files may be unversioned, see `FORMAT_VERSION`
+    if line == header && header == HEADER_TOP.trim() {
+        return Ok(1);
+    }
+
+    let version = line
+        .strip_prefix(header)
+        .and_then(|rest| rest.strip_prefix(' '))
+        .and_then(|version| version.parse::<u32>().ok())
+        .filter(|version| *version >= 2)
+        .ok_or_else(|| {
+            DecodeError::Layout(LayoutError {
+                line: Some(1),
+                expected: format!("`{}` followed by a format version", header),
+                found: Some(format!("`{}`", line)),
+            })
+        })?;
+
+    assert_or(version <= FORMAT_VERSION, DecodeError::Version(version))?;
+
+    Ok(version)
+}
+
+/// The bytes a signature is made over, from version 2 of the format
+///
+/// The first line of the document is included, so a signature can't be moved
+/// to a different kind of document, or a different version of the format.
+fn signed_bytes(header: &str, version: u32, body: &Value) -> Vec<u8> {
+    let mut signed = format!("{} {}\n", header.trim(), version);
+    signed += &canonical(body);
+    signed.into_bytes()
+}
+
+/// Check that the line at `index` is the header `expected`
+pub(crate) fn expect_line(lines: &[&str], index: usize, expected: &str) -> Result<(), DecodeError> {
+    assert_or(
+        lines[index] == expected.trim(),
+        DecodeError::Layout(LayoutError {
+            line: Some(index + 1),
+            expected: format!("`{}`", expected.trim()),
+            found: Some(format!("`{}`", lines[index])),
+        }),
+    )
+}
+
+/// Deserialize a TOML body that follows `skipped` lines of the document
+///
+/// Line numbers in any errors count from the top of the document, not the body.
+pub(crate) fn parse_toml<T: DeserializeOwned>(
+    body: &str,
+    skipped: usize,
+) -> Result<T, DecodeError> {
+    let padded = "\n".repeat(skipped) + body;
+    toml::from_str(&padded).map_err(DecodeError::Toml)
+}
+
+/// Render an armored document from its body and signature
+///
+/// The first line is marked with the current [`FORMAT_VERSION`].
+pub(crate) fn armor(header: &str, body: &str, signature: &Signature) -> String {
+    let mut contents = String::new();
+    contents += &format!("{} {}\n", header.trim(), FORMAT_VERSION);
+    contents += body;
+    contents += HEADER_SIGNATURE;
+    contents += &b64_encode(&signature.to_bytes()[..]);
+    contents += "\n";
+    contents += HEADER_END_OF_FILE;
+    contents
+}
+
+/// Parse a base64 encoded signature, found on `line` of a document
+pub(crate) fn parse_signature(input: &str, line: Option<usize>) -> Result<Signature, DecodeError> {
+    // TODO: The rest of this check probably should just be serde?
+    let sig_decoded = b64_decode(input.trim()).map_err(|_| DecodeError::Signature { line })?;
+
+    assert_or(
+        sig_decoded.len() == ed25519_dalek::SIGNATURE_LENGTH,
+        DecodeError::Signature { line },
+    )?;
+
+    Signature::from_bytes(sig_decoded.as_ref()).map_err(|_| DecodeError::Signature { line })
+}
+
+/// Parse a base64 encoded public key, found on `line` of a document
+pub(crate) fn parse_public_key(input: &str, line: Option<usize>) -> Result<PublicKey, DecodeError> {
+    let pubkey_bytes = b64_decode(input).map_err(|_| DecodeError::PublicKey { line })?;
+    PublicKey::from_bytes(&pubkey_bytes).map_err(|_| DecodeError::PublicKey { line })
+}
+
+/// Check that `signature` was made over `data` by the base64 encoded `pubkey`
+///
+/// `line` is where the signature was found, for reporting a mismatch.
+pub(crate) fn check_signature(
+    pubkey: &str,
+    data: &[u8],
+    signature: &Signature,
+    line: Option<usize>,
+) -> Result<(), DecodeError> {
+    let public_key = parse_public_key(pubkey, None)?;
+
+    let good_sig = public_key.verify(data, signature).is_ok();
+
+    assert_or(good_sig, DecodeError::Verification { line })
+}
+
+/// Decode a frauth file, checking that it is signed by the expected public key
+///
+/// `pubkey` is the base64 encoded public key, as it appears in published files.
+pub fn verify(input: &str, pubkey: &str) -> Result<PublishUserInfo, DecodeError> {
+    let pub_info = decode(input)?;
+
+    assert_or(pub_info.pubkey == pubkey, DecodeError::PublicKeyMismatch)?;
+
+    Ok(pub_info)
+}
+
+/// Any document that may be published at an identity URL
+#[derive(Debug)]
+pub enum Document {
+    /// A normal frauth file
+    Identity(PublishUserInfo),
+
+    /// An announcement that the key for this identity should no longer be trusted
+    Revocation(Revocation),
+}
+
+/// Decode whichever document is published at an identity URL
+///
+/// Each document is checked to be signed by the public key it contains.
+pub fn decode_document(input: &str) -> Result<Document, DecodeError> {
+    // Leading whitespace is ignored when unarmoring, so it is here too
+    if input.trim_start().starts_with(HEADER_REVOCATION.trim()) {
+        Revocation::decode(input).map(Document::Revocation)
+    } else {
+        decode(input).map(Document::Identity)
+    }
+}
+
+pub(crate) fn assert_or<E>(me: bool, err: E) -> Result<(), E> {
+    if me {
+        Ok(())
+    } else {
+        Err(err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{rotation::KeySuccession, schema::PublishFriend};
+    use chrono::{TimeZone, Utc};
+    use rand::rngs::OsRng;
+
+    fn pub_info(keypair: &Keypair) -> PublishUserInfo {
+        let old = Keypair::generate(&mut OsRng);
+        let pubkey = b64_encode(keypair.public.as_bytes());
+
+        PublishUserInfo {
+            name: "Ada \"the first\" Lovelace".into(),
+            status: Some("tabs\tand\nnewlines, \\ and ünïcødé".into()),
+            successions: vec![KeySuccession::new(&old, &pubkey)],
+            pubkey,
+            last_updated: Some(Utc.ymd(2020, 1, 2).and_hms_milli(3, 4, 5, 678)),
+            expires: Some(Utc.ymd(2021, 1, 2).and_hms(3, 4, 5)),
+            friends: vec![PublishFriend {
+                uri: "https://example.com/me.frauth".into(),
+                pubkey: b64_encode(old.public.as_bytes()),
+                trust: None,
+            }],
+            identities: vec![("github".to_string(), "ada".to_string())]
+                .into_iter()
+                .collect(),
+        }
+    }
+
+    /// Armor `body` the way frauth 0.2.x did, signing it exactly as written
+    fn encode_v1(body: &str, keypair: &Keypair) -> String {
+        let sig = keypair.sign(body.as_bytes());
+        format!(
+            "{}{}{}{}\n{}",
+            HEADER_TOP,
+            body,
+            HEADER_SIGNATURE,
+            b64_encode(&sig.to_bytes()[..]),
+            HEADER_END_OF_FILE
+        )
+    }
+
+    #[test]
+    fn round_trip() {
+        let keypair = Keypair::generate(&mut OsRng);
+        let original = pub_info(&keypair);
+
+        let encoded = encode(&original, &keypair).unwrap();
+        assert!(encoded.starts_with("FRAUTH-CONTENTS 2\n"));
+        assert_eq!(document_version(&encoded).unwrap(), 2);
+
+        let decoded = verify(&encoded, &original.pubkey).unwrap();
+        assert_eq!(decoded.name, original.name);
+        assert_eq!(decoded.status, original.status);
+        assert_eq!(decoded.last_updated, original.last_updated);
+        assert_eq!(decoded.expires, original.expires);
+        assert_eq!(decoded.identities, original.identities);
+        assert_eq!(decoded.successions, original.successions);
+        assert_eq!(decoded.friends[0].uri, original.friends[0].uri);
+    }
+
+    #[test]
+    fn reformatting_still_verifies() {
+        let keypair = Keypair::generate(&mut OsRng);
+        let encoded = encode(&pub_info(&keypair), &keypair).unwrap();
+
+        let crlf = encoded.replace('\n', "\r\n");
+        decode(&crlf).unwrap();
+
+        // Reformat the body, keeping the same contents
+        let lines = encoded.lines().collect::<Vec<_>>();
+        let (last, body) = lines.split_last().unwrap();
+        let (sig, body) = body.split_last().unwrap();
+        let (sig_header, body) = body.split_last().unwrap();
+        let (header, body) = body.split_first().unwrap();
+        let reformatted = body
+            .iter()
+            .map(|line| line.replacen(" = ", "   =   ", 1))
+            .flat_map(|line| vec![line, String::new()])
+            .collect::<Vec<_>>()
+            .join("\n");
+        let reformatted = format!(
+            "\n  {}\n# A comment\n{}\n{}\n{}\n{}\n\n",
+            header, reformatted, sig_header, sig, last
+        );
+
+        decode(&reformatted).unwrap();
+    }
+
+    #[test]
+    fn changing_contents_breaks_signature() {
+        let keypair = Keypair::generate(&mut OsRng);
+        let encoded = encode(&pub_info(&keypair), &keypair).unwrap();
+
+        let tampered = encoded.replace("github = \"ada\"", "github = \"eve\"");
+        assert_ne!(tampered, encoded);
+        assert!(matches!(
+            decode(&tampered),
+            Err(DecodeError::Verification { .. })
+        ));
+    }
+
+    #[test]
+    fn changing_version_breaks_signature() {
+        let keypair = Keypair::generate(&mut OsRng);
+        let encoded = encode(&pub_info(&keypair), &keypair).unwrap();
+
+        // Claiming to be a version 1 file changes what the signature covers
+        let downgraded = encoded.replacen("FRAUTH-CONTENTS 2\n", HEADER_TOP, 1);
+        assert_eq!(document_version(&downgraded).unwrap(), 1);
+        assert!(matches!(
+            decode(&downgraded),
+            Err(DecodeError::Verification { .. })
+        ));
+
+        let newer = encoded.replacen("FRAUTH-CONTENTS 2\n", "FRAUTH-CONTENTS 3\n", 1);
+        assert!(matches!(decode(&newer), Err(DecodeError::Version(3))));
+    }
+
+    #[test]
+    fn version_1_files_still_decode() {
+        let keypair = Keypair::generate(&mut OsRng);
+        let pubkey = b64_encode(keypair.public.as_bytes());
+
+        // As written by frauth 0.2.x, without `last_updated`
+        let body = format!(
+            "name = \"Ada\"\npubkey = \"{}\"\nfriends = []\n\n[identities]\ngithub = \"ada\"\n",
+            pubkey
+        );
+        let encoded = encode_v1(&body, &keypair);
+
+        let decoded = verify(&encoded, &pubkey).unwrap();
+        assert_eq!(decoded.name, "Ada");
+        assert_eq!(decoded.last_updated, None);
+
+        // Version 1 files are signed exactly as written
+        let reformatted = encoded.replace("name = ", "name=");
+        assert!(matches!(
+            decode(&reformatted),
+            Err(DecodeError::Verification { .. })
+        ));
+    }
+
+    #[test]
+    fn only_frauth_files_may_be_unversioned() {
+        let keypair = Keypair::generate(&mut OsRng);
+        let revocation = Revocation::new(&keypair, None).render(&keypair).unwrap();
+        decode_document(&revocation).unwrap();
+
+        let unversioned = revocation.replacen("FRAUTH-REVOCATION 2\n", HEADER_REVOCATION, 1);
+        assert!(matches!(
+            decode_document(&unversioned),
+            Err(DecodeError::Layout(LayoutError { line: Some(1), .. }))
+        ));
+        assert!(document_version(&unversioned).is_err());
+    }
+
+    #[test]
+    fn revocations_may_have_leading_whitespace() {
+        let keypair = Keypair::generate(&mut OsRng);
+        let revocation = Revocation::new(&keypair, None).render(&keypair).unwrap();
+
+        let padded = format!("\n  \n\t{}", revocation);
+        assert!(matches!(
+            decode_document(&padded),
+            Ok(Document::Revocation(_))
+        ));
+        assert_eq!(document_version(&padded).unwrap(), FORMAT_VERSION);
+    }
+}
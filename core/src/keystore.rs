@@ -0,0 +1,145 @@
+//! Passphrase protection for secret keys at rest
+//!
+//! The secret key is encrypted with a key derived from the passphrase using
+//! Argon2id, a memory-hard key derivation function. The public key is kept
+//! in the clear, so that it can be used without the passphrase.
+
+use argon2::{Algorithm, Argon2, Params, Version};
+use base64::{decode as b64_decode, encode as b64_encode};
+use ed25519_dalek::{Keypair, PublicKey, SecretKey};
+use rand::{rngs::OsRng, RngCore};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    crypt::{open, seal},
+    error::CryptError,
+};
+
+/// Memory cost in KiB
+const M_COST: u32 = 19 * 1024;
+/// Number of iterations
+const T_COST: u32 = 2;
+/// Degree of parallelism
+const P_COST: u32 = 1;
+
+/// A keypair, with the secret key encrypted by a passphrase
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct EncryptedKey {
+    /// The base64 encoded public key
+    pub pubkey: String,
+    /// The base64 encoded, encrypted secret key
+    secret: String,
+    /// The base64 encoded salt used when deriving the key
+    salt: String,
+    m_cost: u32,
+    t_cost: u32,
+    p_cost: u32,
+}
+
+impl EncryptedKey {
+    /// Encrypt the secret key of `keypair` with `passphrase`
+    pub fn seal(keypair: &Keypair, passphrase: &str) -> Result<Self, CryptError> {
+        let mut salt = [0u8; 16];
+        OsRng.fill_bytes(&mut salt);
+
+        let key = derive_key(passphrase, &salt, M_COST, T_COST, P_COST)?;
+
+        // The salt is fresh every time, so each derived key only encrypts one secret
+        let secret = seal(&key, keypair.secret.as_bytes())?;
+
+        Ok(EncryptedKey {
+            pubkey: b64_encode(keypair.public.as_bytes()),
+            secret: b64_encode(&secret),
+            salt: b64_encode(&salt),
+            m_cost: M_COST,
+            t_cost: T_COST,
+            p_cost: P_COST,
+        })
+    }
+
+    /// Decrypt the keypair with `passphrase`
+    ///
+    /// Returns [`CryptError::Decryption`] if the passphrase is wrong.
+    pub fn open(&self, passphrase: &str) -> Result<Keypair, CryptError> {
+        let salt = b64_decode(&self.salt).map_err(|_| CryptError::Decryption)?;
+        let sealed = b64_decode(&self.secret).map_err(|_| CryptError::Decryption)?;
+
+        let key = derive_key(passphrase, &salt, self.m_cost, self.t_cost, self.p_cost)?;
+        let secret_bytes = open(&key, &sealed)?;
+
+        let secret = SecretKey::from_bytes(&secret_bytes).map_err(|_| CryptError::Decryption)?;
+        let public = PublicKey::from(&secret);
+
+        if b64_encode(public.as_bytes()) != self.pubkey {
+            return Err(CryptError::PublicKey);
+        }
+
+        Ok(Keypair { secret, public })
+    }
+}
+
+fn derive_key(
+    passphrase: &str,
+    salt: &[u8],
+    m_cost: u32,
+    t_cost: u32,
+    p_cost: u32,
+) -> Result<[u8; 32], CryptError> {
+    let params =
+        Params::new(m_cost, t_cost, p_cost, Some(32)).map_err(|_| CryptError::Decryption)?;
+
+    let mut key = [0u8; 32];
+    Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+        .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+        .map_err(|_| CryptError::Decryption)?;
+
+    Ok(key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn seal_and_open() {
+        let keypair = Keypair::generate(&mut OsRng);
+
+        let sealed = EncryptedKey::seal(&keypair, "correct horse").unwrap();
+        assert_eq!(sealed.pubkey, b64_encode(keypair.public.as_bytes()));
+
+        // The secret must not be stored in the clear
+        assert!(!sealed
+            .secret
+            .contains(&b64_encode(keypair.secret.as_bytes())));
+
+        let opened = sealed.open("correct horse").unwrap();
+        assert_eq!(opened.to_bytes()[..], keypair.to_bytes()[..]);
+    }
+
+    #[test]
+    fn wrong_passphrase_is_rejected() {
+        let keypair = Keypair::generate(&mut OsRng);
+
+        let sealed = EncryptedKey::seal(&keypair, "correct horse").unwrap();
+
+        assert!(matches!(
+            sealed.open("battery staple"),
+            Err(CryptError::Decryption)
+        ));
+        assert!(matches!(sealed.open(""), Err(CryptError::Decryption)));
+    }
+
+    #[test]
+    fn mismatched_public_key_is_rejected() {
+        let keypair = Keypair::generate(&mut OsRng);
+        let other = Keypair::generate(&mut OsRng);
+
+        let mut sealed = EncryptedKey::seal(&keypair, "correct horse").unwrap();
+        sealed.pubkey = b64_encode(other.public.as_bytes());
+
+        assert!(matches!(
+            sealed.open("correct horse"),
+            Err(CryptError::PublicKey)
+        ));
+    }
+}
@@ -0,0 +1,33 @@
+//! Core types and routines for working with frauth files.
+//!
+//! A frauth file is a signed, plain text document that a user hosts on a
+//! static website. The URL of that file becomes their identity. This crate
+//! contains everything needed to render, decode, and verify these files
+//! without going through the `frauth` CLI.
+
+pub mod canonical;
+pub mod crypt;
+pub mod detached;
+pub mod error;
+pub mod file;
+pub mod keystore;
+pub mod message;
+pub mod revocation;
+pub mod rotation;
+pub mod schema;
+pub mod score;
+
+#[cfg(test)]
+mod testing;
+
+pub use crate::{
+    crypt::EncryptedFile,
+    detached::DetachedSignature,
+    error::{CryptError, DecodeError, EncodeError, LayoutError},
+    file::{decode, decode_document, document_version, encode, verify, Document, FORMAT_VERSION},
+    keystore::EncryptedKey,
+    message::SignedMessage,
+    revocation::Revocation,
+    rotation::{follow_successions, KeySuccession},
+    schema::{PublishFriend, PublishUserInfo, Trust},
+};
@@ -0,0 +1,180 @@
+use base64::encode as b64_encode;
+use ed25519_dalek::{Keypair, Signature};
+
+use crate::{
+    error::DecodeError,
+    file::{
+        assert_or, check_signature, expect_line, parse_signature, HEADER_END_OF_FILE,
+        HEADER_SIGNATURE, HEADER_TOP,
+    },
+};
+
+pub const HEADER_MESSAGE: &str = "FRAUTH-MESSAGE\n";
+
+/// A short text message, signed inline ("clearsigned")
+///
+/// The URL of the signer is included, so that the recipient can look up the
+/// public key of the signer.
+#[derive(Debug)]
+pub struct SignedMessage {
+    pub signer_url: String,
+    pub body: String,
+    pub signature: Signature,
+}
+
+impl SignedMessage {
+    /// Sign a message with the given keypair
+    ///
+    /// Line endings in `body` are normalized to `\n`, and a trailing newline
+    /// is added if necessary.
+    pub fn sign(signer_url: &str, body: &str, keypair: &Keypair) -> Self {
+        let signer_url = signer_url.trim().to_string();
+        let body = normalize(&body.lines().collect::<Vec<_>>());
+        let signature = keypair.sign(signed_bytes(&signer_url, &body).as_bytes());
+
+        SignedMessage {
+            signer_url,
+            body,
+            signature,
+        }
+    }
+
+    /// Render the message, suitable for pasting into an email or chat
+    pub fn render(&self) -> String {
+        let mut contents = String::new();
+        contents += HEADER_MESSAGE;
+        contents += &self.signer_url;
+        contents += "\n";
+        contents += HEADER_TOP;
+        contents += &self.body;
+        contents += HEADER_SIGNATURE;
+        contents += &b64_encode(&self.signature.to_bytes()[..]);
+        contents += "\n";
+        contents += HEADER_END_OF_FILE;
+        contents
+    }
+
+    /// Parse a rendered message
+    ///
+    /// This does not check the signature. Look up the public key for
+    /// `signer_url`, then use [`SignedMessage::verify`] for that.
+    pub fn parse(input: &str) -> Result<Self, DecodeError> {
+        let lines = input.trim().lines().collect::<Vec<_>>();
+
+        // We need at least:
+        // * A message header
+        // * The signer URL
+        // * A contents header
+        // * At least one body line
+        // * A signature header
+        // * A signature
+        // * An End of File footer
+        assert_or(
+            lines.len() >= 7,
+            DecodeError::layout("at least 7 lines", format!("{}", lines.len())),
+        )?;
+
+        let len = lines.len();
+        expect_line(&lines, 0, HEADER_MESSAGE)?;
+        expect_line(&lines, 2, HEADER_TOP)?;
+        expect_line(&lines, len - 3, HEADER_SIGNATURE)?;
+        expect_line(&lines, len - 1, HEADER_END_OF_FILE)?;
+
+        let signer_url = lines[1].trim().to_string();
+        assert_or(
+            !signer_url.is_empty(),
+            DecodeError::layout_at(1, "the URL of the signer"),
+        )?;
+
+        let signature = parse_signature(lines[len - 2], Some(len - 1))?;
+
+        Ok(SignedMessage {
+            signer_url,
+            body: normalize(&lines[3..len - 3]),
+            signature,
+        })
+    }
+
+    /// Check that this message was signed by `pubkey`
+    ///
+    /// `pubkey` is the base64 encoded public key, as it appears in published files.
+    pub fn verify(&self, pubkey: &str) -> Result<(), DecodeError> {
+        // As rendered, the signature follows the five lines around the body
+        let signature_line = self.body.lines().count() + 5;
+
+        check_signature(
+            pubkey,
+            signed_bytes(&self.signer_url, &self.body).as_bytes(),
+            &self.signature,
+            Some(signature_line),
+        )
+    }
+}
+
+fn normalize(lines: &[&str]) -> String {
+    let mut body = lines.join("\n");
+    body += "\n";
+    body
+}
+
+/// The signature covers the signer URL as well as the body, so the URL can't
+/// be swapped out for another identity that shares the same key
+fn signed_bytes(signer_url: &str, body: &str) -> String {
+    let mut signed = String::new();
+    signed += HEADER_MESSAGE;
+    signed += signer_url;
+    signed += "\n";
+    signed += body;
+    signed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::keypair;
+
+    const URL: &str = "https://example.com/ada.frauth";
+
+    #[test]
+    fn round_trip() {
+        let (signer, pubkey) = keypair();
+
+        let message = SignedMessage::sign(URL, "hello\r\nworld", &signer);
+        let parsed = SignedMessage::parse(&message.render()).unwrap();
+
+        assert_eq!(parsed.signer_url, URL);
+        assert_eq!(parsed.body, "hello\nworld\n");
+        parsed.verify(&pubkey).unwrap();
+
+        let (_, other_pubkey) = keypair();
+        assert!(parsed.verify(&other_pubkey).is_err());
+    }
+
+    #[test]
+    fn tampered_body_is_rejected() {
+        let (signer, pubkey) = keypair();
+
+        let rendered = SignedMessage::sign(URL, "pay 10 coins", &signer).render();
+        let tampered = rendered.replace("pay 10 coins", "pay 99 coins");
+        assert_ne!(rendered, tampered);
+
+        let parsed = SignedMessage::parse(&tampered).unwrap();
+        assert!(matches!(
+            parsed.verify(&pubkey),
+            Err(DecodeError::Verification { line: Some(6) })
+        ));
+    }
+
+    #[test]
+    fn swapped_url_is_rejected() {
+        let (signer, pubkey) = keypair();
+
+        // The same key may be used for several identities, so the URL is signed too
+        let rendered = SignedMessage::sign(URL, "hello", &signer).render();
+        let swapped = rendered.replace(URL, "https://example.com/mallory.frauth");
+        assert_ne!(rendered, swapped);
+
+        let parsed = SignedMessage::parse(&swapped).unwrap();
+        assert!(parsed.verify(&pubkey).is_err());
+    }
+}
@@ -0,0 +1,61 @@
+use base64::encode as b64_encode;
+use chrono::{DateTime, Utc};
+use ed25519_dalek::Keypair;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::{DecodeError, EncodeError},
+    file::{check_signature, parse_toml, sign_and_armor, unarmor},
+};
+
+pub const HEADER_REVOCATION: &str = "FRAUTH-REVOCATION\n";
+
+/// An announcement, signed by a key, that the key should no longer be trusted
+///
+/// This can be published at an identity URL in place of the normal frauth file.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Revocation {
+    /// The base64 encoded public key being revoked
+    pub pubkey: String,
+    pub revoked_at: DateTime<Utc>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+}
+
+impl Revocation {
+    /// Create a revocation for the given keypair
+    pub fn new(keypair: &Keypair, reason: Option<String>) -> Self {
+        Revocation {
+            pubkey: b64_encode(keypair.public.as_bytes()),
+            revoked_at: Utc::now(),
+            reason,
+        }
+    }
+
+    /// Render and sign the revocation, suitable for publishing
+    ///
+    /// The key being revoked must belong to `keypair`.
+    pub fn render(&self, keypair: &Keypair) -> Result<String, EncodeError> {
+        if self.pubkey != b64_encode(keypair.public.as_bytes()) {
+            return Err(EncodeError::PublicKeyMismatch);
+        }
+
+        sign_and_armor(HEADER_REVOCATION, self, keypair)
+    }
+
+    /// Decode a revocation, checking that it is signed by the key it revokes
+    pub fn decode(input: &str) -> Result<Self, DecodeError> {
+        let armored = unarmor(input, HEADER_REVOCATION)?;
+
+        let revocation: Revocation = parse_toml(&armored.body, 1)?;
+
+        check_signature(
+            &revocation.pubkey,
+            &armored.signed,
+            &armored.signature,
+            Some(armored.signature_line),
+        )?;
+
+        Ok(revocation)
+    }
+}
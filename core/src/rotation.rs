@@ -0,0 +1,179 @@
+use base64::encode as b64_encode;
+use ed25519_dalek::Keypair;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::DecodeError,
+    file::{check_signature, parse_signature},
+};
+
+pub const HEADER_SUCCESSION: &str = "FRAUTH-SUCCESSION\n";
+
+/// A statement, signed by an old key, naming the key that replaces it
+///
+/// These are carried in published files, so that friends who only know
+/// the old key can follow a key rotation without losing trust.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct KeySuccession {
+    /// The base64 encoded public key being replaced
+    pub old: String,
+    /// The base64 encoded public key replacing it
+    pub new: String,
+    /// The base64 encoded signature, made by `old`
+    pub signature: String,
+}
+
+impl KeySuccession {
+    /// Create a statement that `new` replaces the key of `old`
+    ///
+    /// `new` is the base64 encoded public key, as it appears in published files.
+    pub fn new(old: &Keypair, new: &str) -> Self {
+        let old_pubkey = b64_encode(old.public.as_bytes());
+        let signature = old.sign(signed_bytes(&old_pubkey, new).as_bytes());
+
+        KeySuccession {
+            old: old_pubkey,
+            new: new.to_string(),
+            signature: b64_encode(&signature.to_bytes()[..]),
+        }
+    }
+
+    /// Check that this statement was signed by the old key
+    pub fn verify(&self) -> Result<(), DecodeError> {
+        check_signature(
+            &self.old,
+            signed_bytes(&self.old, &self.new).as_bytes(),
+            &parse_signature(&self.signature, None)?,
+            None,
+        )
+    }
+}
+
+/// Follow a chain of valid successions from the key `from` to the key `to`
+///
+/// On success, returns every key that was replaced along the way, starting
+/// with `from`. Returns `None` if there is no chain of validly signed
+/// successions between the two keys.
+pub fn follow_successions(
+    successions: &[KeySuccession],
+    from: &str,
+    to: &str,
+) -> Option<Vec<String>> {
+    let mut replaced = Vec::new();
+    let mut current = from;
+
+    // Each succession can only be used once, which also prevents cycles
+    while current != to {
+        if replaced.len() >= successions.len() {
+            return None;
+        }
+
+        let next = successions
+            .iter()
+            .find(|s| s.old == current && s.verify().is_ok())?;
+
+        replaced.push(current.to_string());
+        current = &next.new;
+    }
+
+    Some(replaced)
+}
+
+fn signed_bytes(old: &str, new: &str) -> String {
+    let mut signed = String::new();
+    signed += HEADER_SUCCESSION;
+    signed += old;
+    signed += "\n";
+    signed += new;
+    signed += "\n";
+    signed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::keypair;
+
+    #[test]
+    fn follows_a_chain() {
+        let (first, first_pubkey) = keypair();
+        let (second, second_pubkey) = keypair();
+        let (_, third_pubkey) = keypair();
+
+        // Order in the file shouldn't matter
+        let successions = vec![
+            KeySuccession::new(&second, &third_pubkey),
+            KeySuccession::new(&first, &second_pubkey),
+        ];
+
+        assert_eq!(
+            follow_successions(&successions, &first_pubkey, &third_pubkey),
+            Some(vec![first_pubkey.clone(), second_pubkey.clone()])
+        );
+        assert_eq!(
+            follow_successions(&successions, &second_pubkey, &third_pubkey),
+            Some(vec![second_pubkey.clone()])
+        );
+        assert_eq!(
+            follow_successions(&successions, &third_pubkey, &third_pubkey),
+            Some(vec![])
+        );
+
+        // Successions only go forwards
+        assert_eq!(
+            follow_successions(&successions, &third_pubkey, &first_pubkey),
+            None
+        );
+    }
+
+    #[test]
+    fn cycles_end() {
+        let (first, first_pubkey) = keypair();
+        let (second, second_pubkey) = keypair();
+        let (_, unrelated_pubkey) = keypair();
+
+        let successions = vec![
+            KeySuccession::new(&first, &second_pubkey),
+            KeySuccession::new(&second, &first_pubkey),
+        ];
+
+        assert_eq!(
+            follow_successions(&successions, &first_pubkey, &unrelated_pubkey),
+            None
+        );
+    }
+
+    #[test]
+    fn bad_signatures_are_not_followed() {
+        let (first, first_pubkey) = keypair();
+        let (_, second_pubkey) = keypair();
+        let (_, mallory_pubkey) = keypair();
+
+        // Signed by the wrong key
+        let (mallory, _) = keypair();
+        let mut forged = KeySuccession::new(&mallory, &mallory_pubkey);
+        forged.old = first_pubkey.clone();
+        assert!(forged.verify().is_err());
+        assert_eq!(
+            follow_successions(&[forged], &first_pubkey, &mallory_pubkey),
+            None
+        );
+
+        // A valid signature, moved to a different new key
+        let mut redirected = KeySuccession::new(&first, &second_pubkey);
+        redirected.new = mallory_pubkey.clone();
+        assert!(redirected.verify().is_err());
+        assert_eq!(
+            follow_successions(&[redirected], &first_pubkey, &mallory_pubkey),
+            None
+        );
+
+        // Not a signature at all
+        let mut garbage = KeySuccession::new(&first, &second_pubkey);
+        garbage.signature = "not a signature".into();
+        assert_eq!(
+            follow_successions(&[garbage], &first_pubkey, &second_pubkey),
+            None
+        );
+    }
+}
@@ -0,0 +1,71 @@
+use std::{collections::BTreeMap, fmt};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::rotation::KeySuccession;
+
+/// The contents of a published frauth file
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PublishUserInfo {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status: Option<String>,
+    pub pubkey: String,
+
+    /// When the file was published. Always set from format version 2, but
+    /// files from frauth 0.2.x may leave it out
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_updated: Option<DateTime<Utc>>,
+
+    /// After this, the file is stale and should be re-published
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires: Option<DateTime<Utc>>,
+
+    pub friends: Vec<PublishFriend>,
+    pub identities: BTreeMap<String, String>,
+
+    /// Statements linking previous keys of this user to their current key
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub successions: Vec<KeySuccession>,
+}
+
+impl PublishUserInfo {
+    /// Whether the file has passed its expiry date, if it has one
+    pub fn is_expired(&self) -> bool {
+        self.expires
+            .map(|expires| expires < Utc::now())
+            .unwrap_or(false)
+    }
+}
+
+/// A friend, as listed in a published frauth file
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PublishFriend {
+    pub uri: String,
+    pub pubkey: String,
+
+    /// How sure the publisher is that `pubkey` belongs to this friend. Only
+    /// set if the publisher chose to share it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub trust: Option<Trust>,
+}
+
+/// How sure someone is that a friend's public key really belongs to them
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Trust {
+    Low,
+    Medium,
+    High,
+}
+
+impl fmt::Display for Trust {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Trust::Low => "low",
+            Trust::Medium => "medium",
+            Trust::High => "high",
+        })
+    }
+}
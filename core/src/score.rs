@@ -0,0 +1,108 @@
+//! Scoring how much to trust a peer, based on who vouches for them
+
+use crate::schema::Trust;
+
+/// Score a peer, based on how many people vouch for them at each depth
+///
+/// `counts[0]` is whether the peer is a direct friend (`0` or `1`),
+/// `counts[1]` is how many of your friends list the peer as their friend,
+/// `counts[2]` is how many friends-of-friends list the peer, and so on.
+///
+/// A direct friend always scores `1.0`. Otherwise, each depth contributes
+/// up to half as much as the depth before it, with each extra voucher at
+/// that depth closing half of the remaining gap. The score is always in
+/// the range `0.0..1.0`.
+pub fn score(counts: &[usize]) -> f64 {
+    let weights = counts.iter().map(|ct| *ct as f64).collect::<Vec<_>>();
+    weighted_score(&weights)
+}
+
+/// Like [`score`], but each voucher counts for the weight of their trust in
+/// the peer, rather than for `1`
+///
+/// `weights[i]` is the sum of the weights of everyone vouching at depth `i`.
+/// See [`trust_weight`].
+pub fn weighted_score(weights: &[f64]) -> f64 {
+    if weights.is_empty() {
+        return 0f64;
+    }
+
+    if weights[0] != 0f64 {
+        return 1.0f64;
+    }
+
+    let mut score = 0f64;
+
+    for (i, weight) in weights.iter().enumerate() {
+        if *weight == 0f64 {
+            continue;
+        }
+
+        let depth_weight = 1f64 / 2f64.powf(i as f64);
+        let vouched = 1f64 - (1f64 / 2f64.powf(*weight));
+
+        score += depth_weight * vouched;
+    }
+
+    score
+}
+
+/// How much a voucher counts for, given their trust in the peer
+///
+/// Files from before trust levels existed, and people who don't share
+/// them, count fully, the same as high trust.
+pub fn trust_weight(trust: Option<Trust>) -> f64 {
+    match trust {
+        Some(Trust::Low) => 0.25,
+        Some(Trust::Medium) => 0.5,
+        Some(Trust::High) | None => 1.0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn trust_levels_have_increasing_weights() {
+        assert_eq!(trust_weight(Some(Trust::Low)), 0.25);
+        assert_eq!(trust_weight(Some(Trust::Medium)), 0.5);
+        assert_eq!(trust_weight(Some(Trust::High)), 1.0);
+    }
+
+    #[test]
+    fn unpublished_trust_counts_fully() {
+        assert_eq!(trust_weight(None), trust_weight(Some(Trust::High)));
+        assert_eq!(weighted_score(&[0.0, trust_weight(None)]), score(&[0, 1]));
+    }
+
+    #[test]
+    fn friends_trust_scales_their_vouch() {
+        // A friend we trust little, vouching with high trust, counts for less
+        // than a friend we trust highly
+        let vouch = trust_weight(Some(Trust::High));
+        let low = weighted_score(&[0.0, trust_weight(Some(Trust::Low)) * vouch]);
+        let medium = weighted_score(&[0.0, trust_weight(Some(Trust::Medium)) * vouch]);
+        let high = weighted_score(&[0.0, trust_weight(Some(Trust::High)) * vouch]);
+
+        assert!(0.0 < low && low < medium && medium < high);
+        assert_eq!(high, 0.25);
+
+        // Four vouchers at a quarter weight add up to one at full weight
+        assert_eq!(
+            weighted_score(&[0.0, 4.0 * trust_weight(Some(Trust::Low))]),
+            high
+        );
+    }
+
+    #[test]
+    fn direct_friends_and_strangers() {
+        assert_eq!(score(&[]), 0.0);
+        assert_eq!(score(&[0, 0, 0]), 0.0);
+        assert_eq!(score(&[1, 0, 5]), 1.0);
+        assert_eq!(score(&[0, 1]), 0.25);
+        assert_eq!(score(&[0, 2]), 0.375);
+        assert_eq!(score(&[0, 0, 1]), 0.125);
+        assert!(score(&[0, 1000, 1000]) < 1.0);
+    }
+}
@@ -0,0 +1,12 @@
+//! Fixtures shared by the unit tests
+
+use base64::encode as b64_encode;
+use ed25519_dalek::Keypair;
+use rand::rngs::OsRng;
+
+/// A fresh keypair, with its public key base64 encoded as in published files
+pub(crate) fn keypair() -> (Keypair, String) {
+    let keypair = Keypair::generate(&mut OsRng);
+    let pubkey = b64_encode(keypair.public.as_bytes());
+    (keypair, pubkey)
+}